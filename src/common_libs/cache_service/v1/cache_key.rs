@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::config::AppConfig;
+use crate::state::APP_STATE;
+
+// Generations are re-read from Redis at least this often, flushes invalidate them right away
+const GENERATION_CACHE_SECS: u64 = 60;
+
+/// Group of cache keys which are versioned and flushed together.
+///
+/// Bump `version` when the format of the cached values changes.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheNamespace {
+    pub name: &'static str,
+    pub version: u32,
+}
+
+impl CacheNamespace {
+    pub const fn new(name: &'static str, version: u32) -> Self {
+        Self { name, version }
+    }
+}
+
+/// Builds keys as `{gae_service}:{env}:{namespace}:v{version}.{generation}:{parts}`.
+///
+/// The generation is a per-namespace counter stored in Redis. `flush_namespace`
+/// increments it, so all existing keys of the namespace stop being read and
+/// expire on their own.
+pub struct CacheKeyBuilder {
+    prefix: String,
+    // Last generation read per namespace, used while Redis is unavailable
+    last_known: Mutex<HashMap<&'static str, u64>>,
+}
+
+impl CacheKeyBuilder {
+    pub fn new(config: &AppConfig) -> Self {
+        Self {
+            prefix: format!("{}:{}", config.gae_service, config.env),
+            last_known: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn key(&self, namespace: &CacheNamespace, parts: &[&str]) -> String {
+        let generation = self.generation(namespace).await;
+        let mut key = format!("{}:{}:v{}.{}", self.prefix, namespace.name, namespace.version, generation);
+        for part in parts {
+            key.push(':');
+            key.push_str(part);
+        }
+        key
+    }
+
+    /// Invalidate every key of `namespace` on all instances, returns the new generation.
+    #[allow(dead_code)]
+    pub async fn flush_namespace(&self, namespace: &CacheNamespace) -> Result<u64, String> {
+        let app_state = APP_STATE.get().unwrap();
+        let generation_key = self.generation_key(namespace);
+
+        let generation = app_state.redis_client.incr_by(&generation_key, 1).await?.max(0) as u64;
+        self.last_known.lock().unwrap().insert(namespace.name, generation);
+        app_state.instance_cache.invalidate_everywhere(&generation_key).await?;
+
+        tracing::info!("Flushed cache namespace {} - generation: {}", namespace.name, generation);
+        Ok(generation)
+    }
+
+    fn generation_key(&self, namespace: &CacheNamespace) -> String {
+        format!("{}:cache_generation:{}", self.prefix, namespace.name)
+    }
+
+    async fn generation(&self, namespace: &CacheNamespace) -> u64 {
+        let app_state = APP_STATE.get().unwrap();
+        let generation_key = self.generation_key(namespace);
+
+        if let Some(generation) = app_state.instance_cache.get::<u64>(&generation_key) {
+            return *generation;
+        }
+
+        match app_state.redis_client.get::<u64>(&generation_key).await {
+            Ok(generation) => {
+                let generation = generation.unwrap_or_default();
+                app_state.instance_cache.set(&generation_key, generation, GENERATION_CACHE_SECS);
+                self.last_known.lock().unwrap().insert(namespace.name, generation);
+                generation
+            },
+            Err(e) => {
+                tracing::error!("Failed to get cache generation of {} - err: {}", namespace.name, e);
+                self.last_known.lock().unwrap().get(namespace.name).copied().unwrap_or_default()
+            },
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,331 @@
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::config::EvictionPolicy;
+
+// Rough bookkeeping cost of an entry on top of its key and value size hint
+const ENTRY_OVERHEAD_BYTES: usize = 96;
+const SKETCH_DEPTH: usize = 4;
+const SKETCH_MAX_COUNT: u8 = 15;
+
+struct CachedItem {
+    data: Arc<dyn Any + Send + Sync>,
+    expires_at: Instant,
+    size: usize,
+    last_access: u64,
+}
+
+pub(super) enum Lookup {
+    Hit(Arc<dyn Any + Send + Sync>),
+    Expired,
+    Miss,
+}
+
+#[derive(Default)]
+pub(super) struct InsertOutcome {
+    pub stored: bool,
+    // Larger than the whole shard
+    pub oversized: bool,
+    pub evicted: u64,
+    pub expired: u64,
+}
+
+/// One shard of the instance cache, bounded by count and approximate bytes.
+///
+/// The least recently used entry is evicted first. With `EvictionPolicy::TinyLfu`
+/// a new key only replaces it if the key has been requested more often, while
+/// updates of a cached key are always stored.
+pub(super) struct CacheStore {
+    items: HashMap<String, CachedItem>,
+    // Access tick -> key, least recently used first
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    bytes: usize,
+    max_entries: usize,
+    max_bytes: usize,
+    sketch: Option<FrequencySketch>,
+}
+
+impl CacheStore {
+    pub fn new(max_entries: usize, max_bytes: usize, eviction: EvictionPolicy) -> Self {
+        let sketch = match eviction {
+            EvictionPolicy::Lru => None,
+            EvictionPolicy::TinyLfu => Some(FrequencySketch::new(max_entries)),
+        };
+        Self {
+            items: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            bytes: 0,
+            max_entries,
+            max_bytes,
+            sketch,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.items.keys().cloned().collect()
+    }
+
+    pub fn get(&mut self, key: &str, now: Instant) -> Lookup {
+        if let Some(sketch) = &mut self.sketch {
+            sketch.increment(key);
+        }
+
+        let item = match self.items.get_mut(key) {
+            Some(item) => item,
+            None => return Lookup::Miss,
+        };
+        if now >= item.expires_at {
+            self.remove(key);
+            return Lookup::Expired;
+        }
+
+        self.tick += 1;
+        self.recency.remove(&item.last_access);
+        self.recency.insert(self.tick, key.to_string());
+        item.last_access = self.tick;
+        Lookup::Hit(item.data.clone())
+    }
+
+    pub fn insert(
+        &mut self,
+        key: &str,
+        data: Arc<dyn Any + Send + Sync>,
+        expires_at: Instant,
+        size_hint: usize,
+        now: Instant,
+    ) -> InsertOutcome {
+        let mut outcome = InsertOutcome::default();
+        // Updates of a cached key are always admitted, only new keys compete with the victim
+        let replacing = self.remove(key);
+
+        let size = key.len() + size_hint + ENTRY_OVERHEAD_BYTES;
+        if size > self.max_bytes || self.max_entries == 0 {
+            outcome.oversized = size > self.max_bytes;
+            return outcome;
+        }
+
+        while self.items.len() >= self.max_entries || self.bytes + size > self.max_bytes {
+            let victim_key = match self.recency.values().next() {
+                Some(victim_key) => victim_key.clone(),
+                None => break,
+            };
+            if now >= self.items[&victim_key].expires_at {
+                outcome.expired += 1;
+            }
+            else if !replacing && self.sketch.as_ref().is_some_and(|sketch| sketch.estimate(key) <= sketch.estimate(&victim_key)) {
+                // Rejected by TinyLFU admission, the victim is used at least as often
+                return outcome;
+            }
+            else {
+                outcome.evicted += 1;
+            }
+            self.remove(&victim_key);
+        }
+
+        self.tick += 1;
+        self.recency.insert(self.tick, key.to_string());
+        self.items.insert(key.to_string(), CachedItem {
+            data,
+            expires_at,
+            size,
+            last_access: self.tick,
+        });
+        self.bytes += size;
+        outcome.stored = true;
+        outcome
+    }
+
+    pub fn remove(&mut self, key: &str) -> bool {
+        match self.items.remove(key) {
+            Some(item) => {
+                self.recency.remove(&item.last_access);
+                self.bytes -= item.size;
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn remove_where<F>(&mut self, predicate: F) -> u64
+    where
+        F: Fn(&str, Instant) -> bool,
+    {
+        let keys = self.items.iter()
+            .filter(|(key, item)| predicate(key, item.expires_at))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in &keys {
+            self.remove(key);
+        }
+        keys.len() as u64
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.recency.clear();
+        self.bytes = 0;
+    }
+}
+
+/// Count-min sketch of recent key requests with 4 bit counters.
+///
+/// Counters are halved once `sample_size` requests were recorded, so that
+/// formerly popular keys age out.
+struct FrequencySketch {
+    counters: Vec<u8>,
+    width: usize,
+    additions: usize,
+    sample_size: usize,
+}
+
+impl FrequencySketch {
+    fn new(capacity: usize) -> Self {
+        let width = capacity.max(16).next_power_of_two();
+        Self {
+            counters: vec![0; width * SKETCH_DEPTH],
+            width,
+            additions: 0,
+            sample_size: capacity.max(16) * 10,
+        }
+    }
+
+    fn indexes(&self, key: &str) -> [usize; SKETCH_DEPTH] {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        let (h1, h2) = (hash as usize, (hash >> 32) as usize | 1);
+
+        let mut indexes = [0; SKETCH_DEPTH];
+        for (row, index) in indexes.iter_mut().enumerate() {
+            let column = h1.wrapping_add(row.wrapping_mul(h2)) & (self.width - 1);
+            *index = row * self.width + column;
+        }
+        indexes
+    }
+
+    fn increment(&mut self, key: &str) {
+        for index in self.indexes(key) {
+            if self.counters[index] < SKETCH_MAX_COUNT {
+                self.counters[index] += 1;
+            }
+        }
+
+        self.additions += 1;
+        if self.additions >= self.sample_size {
+            for counter in self.counters.iter_mut() {
+                *counter /= 2;
+            }
+            self.additions /= 2;
+        }
+    }
+
+    fn estimate(&self, key: &str) -> u8 {
+        self.indexes(key).iter()
+            .map(|index| self.counters[*index])
+            .min()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn value(data: &str) -> Arc<dyn Any + Send + Sync> {
+        Arc::new(data.to_string())
+    }
+
+    fn cached(store: &mut CacheStore, key: &str, now: Instant) -> Option<String> {
+        match store.get(key, now) {
+            Lookup::Hit(data) => data.downcast_ref::<String>().cloned(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn tiny_lfu_rejects_cold_new_key_when_full() {
+        let now = Instant::now();
+        let expires_at = now + Duration::from_secs(60);
+        let mut store = CacheStore::new(2, usize::MAX, EvictionPolicy::TinyLfu);
+
+        store.insert("a", value("a"), expires_at, 1, now);
+        store.insert("b", value("b"), expires_at, 1, now);
+        for _ in 0..3 {
+            cached(&mut store, "a", now);
+            cached(&mut store, "b", now);
+        }
+
+        let outcome = store.insert("c", value("c"), expires_at, 1, now);
+        assert!(!outcome.stored);
+        assert_eq!(store.len(), 2);
+        assert_eq!(cached(&mut store, "a", now).as_deref(), Some("a"));
+        assert_eq!(cached(&mut store, "b", now).as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn tiny_lfu_admits_update_of_cached_key_when_full() {
+        let now = Instant::now();
+        let expires_at = now + Duration::from_secs(60);
+        let entry_size = 1 + 100 + ENTRY_OVERHEAD_BYTES;
+        let mut store = CacheStore::new(2, entry_size * 2, EvictionPolicy::TinyLfu);
+
+        store.insert("a", value("a1"), expires_at, 100, now);
+        store.insert("b", value("b1"), expires_at, 100, now);
+        for _ in 0..3 {
+            cached(&mut store, "b", now);
+        }
+
+        // The larger value of `a` only fits once `b`, which is used more often, is evicted
+        let outcome = store.insert("a", value("a2"), expires_at, 150, now);
+        assert!(outcome.stored);
+        assert_eq!(outcome.evicted, 1);
+        assert_eq!(cached(&mut store, "a", now).as_deref(), Some("a2"));
+        assert_eq!(cached(&mut store, "b", now), None);
+    }
+
+    #[test]
+    fn lru_evicts_least_recently_used() {
+        let now = Instant::now();
+        let expires_at = now + Duration::from_secs(60);
+        let mut store = CacheStore::new(2, usize::MAX, EvictionPolicy::Lru);
+
+        store.insert("a", value("a"), expires_at, 1, now);
+        store.insert("b", value("b"), expires_at, 1, now);
+        cached(&mut store, "a", now);
+
+        let outcome = store.insert("c", value("c"), expires_at, 1, now);
+        assert!(outcome.stored);
+        assert_eq!(outcome.evicted, 1);
+        assert_eq!(cached(&mut store, "b", now), None);
+        assert_eq!(store.bytes(), 2 * (1 + 1 + ENTRY_OVERHEAD_BYTES));
+    }
+
+    #[test]
+    fn rejects_entry_larger_than_shard() {
+        let now = Instant::now();
+        let mut store = CacheStore::new(2, 1024, EvictionPolicy::Lru);
+
+        let outcome = store.insert("a", value("a"), now + Duration::from_secs(60), 1024, now);
+        assert!(!outcome.stored);
+        assert!(outcome.oversized);
+        assert_eq!(store.len(), 0);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,97 @@
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::Arc;
+
+/// Approximate memory held by a value, counted towards the instance cache byte limit.
+///
+/// Includes the inline size and the heap memory owned by the value.
+pub trait CacheWeight {
+    fn weight(&self) -> usize;
+}
+
+macro_rules! inline_weight {
+    ($($ty:ty),*) => {
+        $(
+            impl CacheWeight for $ty {
+                fn weight(&self) -> usize {
+                    size_of::<$ty>()
+                }
+            }
+        )*
+    };
+}
+
+inline_weight!(bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, ());
+
+impl CacheWeight for String {
+    fn weight(&self) -> usize {
+        size_of::<String>() + self.capacity()
+    }
+}
+
+impl<T: CacheWeight> CacheWeight for Vec<T> {
+    fn weight(&self) -> usize {
+        let spare = self.capacity().saturating_sub(self.len()) * size_of::<T>();
+        size_of::<Vec<T>>() + spare + self.iter().map(CacheWeight::weight).sum::<usize>()
+    }
+}
+
+impl<T: CacheWeight> CacheWeight for Option<T> {
+    fn weight(&self) -> usize {
+        match self {
+            // The inline size of `T` is part of the option's weight
+            Some(value) => size_of::<Option<T>>() - size_of::<T>() + value.weight(),
+            None => size_of::<Option<T>>(),
+        }
+    }
+}
+
+impl<T: CacheWeight> CacheWeight for Arc<T> {
+    fn weight(&self) -> usize {
+        size_of::<Arc<T>>() + (**self).weight()
+    }
+}
+
+impl<K: CacheWeight, V: CacheWeight> CacheWeight for HashMap<K, V> {
+    fn weight(&self) -> usize {
+        // Spare buckets and control bytes are ignored
+        size_of::<HashMap<K, V>>() + self.iter().map(|(key, value)| key.weight() + value.weight()).sum::<usize>()
+    }
+}
+
+impl CacheWeight for JsonValue {
+    fn weight(&self) -> usize {
+        let owned = match self {
+            JsonValue::Null | JsonValue::Bool(_) | JsonValue::Number(_) => 0,
+            JsonValue::String(s) => s.capacity(),
+            JsonValue::Array(values) => values.iter().map(CacheWeight::weight).sum(),
+            JsonValue::Object(map) => map.iter().map(|(key, value)| key.weight() + value.weight()).sum(),
+        };
+        size_of::<JsonValue>() + owned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn counts_heap_memory() {
+        assert_eq!(42_u64.weight(), 8);
+        assert_eq!(String::new().weight(), size_of::<String>());
+        assert!("x".repeat(10_000).weight() >= 10_000);
+        assert!(vec!["x".repeat(1000); 10].weight() >= 10_000);
+        assert!(Some("x".repeat(1000)).weight() >= 1000);
+        assert_eq!(None::<String>.weight(), size_of::<Option<String>>());
+        assert!(HashMap::from([("key".to_string(), "x".repeat(1000))]).weight() >= 1003);
+    }
+
+    #[test]
+    fn counts_nested_json() {
+        let value = json!({"name": "x".repeat(1000), "items": ["y".repeat(1000), 1, null]});
+        assert!(value.weight() >= 2000 + 4 * size_of::<JsonValue>());
+        assert_eq!(json!(null).weight(), size_of::<JsonValue>());
+    }
+}
\ No newline at end of file
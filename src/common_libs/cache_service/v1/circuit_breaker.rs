@@ -0,0 +1,146 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::CircuitBreakerConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    // Calls are rejected without reaching redis
+    Open,
+    // A single trial call decides whether to close or re-open
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitBreakerStats {
+    pub enabled: bool,
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    pub times_opened: u64,
+    pub rejected: u64,
+    pub opened_at: Option<String>,
+}
+
+struct BreakerState {
+    state: CircuitState,
+    consecutive_failures: u32,
+    // When the circuit opened, or when the latest trial call was let through
+    since: Instant,
+    opened_at: Option<String>,
+}
+
+/// Consecutive-failure circuit breaker for redis calls.
+///
+/// Once `failure_threshold` calls in a row failed, calls are rejected for
+/// `open_duration_ms`. After that one trial call is let through per period
+/// until a call succeeds.
+pub struct CircuitBreaker {
+    enabled: bool,
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Mutex<BreakerState>,
+    // Closed without recent failures, lets calls skip the lock
+    healthy: AtomicBool,
+    times_opened: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            failure_threshold: config.failure_threshold.max(1),
+            open_duration: Duration::from_millis(config.open_duration_ms),
+            state: Mutex::new(BreakerState {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                since: Instant::now(),
+                opened_at: None,
+            }),
+            healthy: AtomicBool::new(true),
+            times_opened: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether a call may go through, counted as rejected otherwise.
+    pub fn try_acquire(&self) -> bool {
+        if !self.enabled || self.healthy.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        match state.state {
+            CircuitState::Closed => true,
+            CircuitState::Open | CircuitState::HalfOpen if state.since.elapsed() >= self.open_duration => {
+                if state.state == CircuitState::Open {
+                    tracing::info!("Redis circuit breaker half-open, letting a trial call through");
+                }
+                state.state = CircuitState::HalfOpen;
+                state.since = Instant::now();
+                true
+            },
+            CircuitState::Open | CircuitState::HalfOpen => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                false
+            },
+        }
+    }
+
+    pub fn record_success(&self) {
+        if !self.enabled || self.healthy.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.state != CircuitState::Closed {
+            tracing::info!("Redis circuit breaker closed, redis calls resumed");
+        }
+        state.state = CircuitState::Closed;
+        state.consecutive_failures = 0;
+        state.opened_at = None;
+        self.healthy.store(true, Ordering::Relaxed);
+    }
+
+    pub fn record_failure(&self) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        self.healthy.store(false, Ordering::Relaxed);
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+
+        let trip = match state.state {
+            CircuitState::Closed => state.consecutive_failures >= self.failure_threshold,
+            CircuitState::HalfOpen => true,
+            CircuitState::Open => false,
+        };
+        if trip {
+            tracing::warn!(
+                "Redis circuit breaker opened after {} consecutive failures, rejecting calls for {:?}",
+                state.consecutive_failures, self.open_duration
+            );
+            state.state = CircuitState::Open;
+            state.since = Instant::now();
+            state.opened_at = Some(chrono::Utc::now().to_rfc3339());
+            self.times_opened.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn stats(&self) -> CircuitBreakerStats {
+        let state = self.state.lock().unwrap();
+        CircuitBreakerStats {
+            enabled: self.enabled,
+            state: state.state,
+            consecutive_failures: state.consecutive_failures,
+            times_opened: self.times_opened.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            opened_at: state.opened_at.clone(),
+        }
+    }
+}
\ No newline at end of file
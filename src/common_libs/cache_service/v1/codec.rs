@@ -0,0 +1,200 @@
+use serde::de::{DeserializeOwned, IntoDeserializer, value::Error as ValueError};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use serde_pickle::{DeOptions, SerOptions};
+
+/// Serialization format of a value stored in Redis.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    Json,
+    // Interoperable with the python services
+    Pickle,
+    MsgPack,
+    Bincode,
+    // Plain UTF-8 text, only for string values
+    Utf8,
+}
+
+impl Codec {
+    /// Name of the codec as stored in metadata, as in the configuration.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Json => "json",
+            Codec::Pickle => "pickle",
+            Codec::MsgPack => "msg_pack",
+            Codec::Bincode => "bincode",
+            Codec::Utf8 => "utf8",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Codec> {
+        match name {
+            "json" => Some(Codec::Json),
+            "pickle" => Some(Codec::Pickle),
+            "msg_pack" => Some(Codec::MsgPack),
+            "bincode" => Some(Codec::Bincode),
+            "utf8" => Some(Codec::Utf8),
+            _ => None,
+        }
+    }
+
+    /// Codec named by stored metadata, `fallback` for values written without it.
+    pub fn from_metadata(name: Option<&[u8]>, fallback: Codec) -> Result<Codec, String> {
+        let name = match name {
+            Some(name) => name,
+            None => return Ok(fallback),
+        };
+        match std::str::from_utf8(name).ok().and_then(Codec::from_name) {
+            Some(codec) => Ok(codec),
+            None => Err(format!("Unknown codec: {}", String::from_utf8_lossy(name))),
+        }
+    }
+
+    pub fn encode<T>(&self, value: &T) -> Result<Vec<u8>, String>
+    where
+        T: Serialize,
+    {
+        match self {
+            Codec::Json => match serde_json::to_vec(value) {
+                Ok(bytes) => Ok(bytes),
+                Err(e) => Err(format!("Failed to json encode value: {}", e)),
+            },
+            Codec::Pickle => match serde_pickle::to_vec(value, SerOptions::default()) {
+                Ok(bytes) => Ok(bytes),
+                Err(e) => Err(format!("Failed to pickle value: {}", e)),
+            },
+            Codec::MsgPack => match rmp_serde::to_vec_named(value) {
+                Ok(bytes) => Ok(bytes),
+                Err(e) => Err(format!("Failed to msgpack encode value: {}", e)),
+            },
+            Codec::Bincode => match bincode::serde::encode_to_vec(value, bincode::config::standard()) {
+                Ok(bytes) => Ok(bytes),
+                Err(e) => Err(format!("Failed to bincode encode value: {}", e)),
+            },
+            Codec::Utf8 => match serde_json::to_value(value) {
+                Ok(JsonValue::String(s)) => Ok(s.into_bytes()),
+                Ok(other) => Err(format!("Utf8 codec only supports string values, got: {}", other)),
+                Err(e) => Err(format!("Failed to encode utf8 value: {}", e)),
+            },
+        }
+    }
+
+    pub fn decode<T>(&self, data: &[u8]) -> Result<T, String>
+    where
+        T: DeserializeOwned,
+    {
+        match self {
+            Codec::Json => match serde_json::from_slice::<T>(data) {
+                Ok(value) => Ok(value),
+                Err(e) => Err(format!("Failed to deserialize json data: {}", e)),
+            },
+            Codec::Pickle => match serde_pickle::from_slice::<T>(data, DeOptions::default()) {
+                Ok(value) => Ok(value),
+                Err(e) => Err(format!("Failed to deserialize pickled data: {}", e)),
+            },
+            Codec::MsgPack => match rmp_serde::from_slice::<T>(data) {
+                Ok(value) => Ok(value),
+                Err(e) => Err(format!("Failed to deserialize msgpack data: {}", e)),
+            },
+            Codec::Bincode => match bincode::serde::decode_from_slice::<T, _>(data, bincode::config::standard()) {
+                Ok((value, _)) => Ok(value),
+                Err(e) => Err(format!("Failed to deserialize bincode data: {}", e)),
+            },
+            Codec::Utf8 => {
+                let text = match std::str::from_utf8(data) {
+                    Ok(text) => text,
+                    Err(e) => return Err(format!("Failed to get UTF‑8 data: {}", e)),
+                };
+                match T::deserialize(IntoDeserializer::<ValueError>::into_deserializer(text)) {
+                    Ok(value) => Ok(value),
+                    Err(e) => Err(format!("Failed to deserialize utf8 data: {}", e)),
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Value {
+        name: String,
+        count: i64,
+        ratio: f64,
+        tags: Vec<String>,
+        extra: Option<HashMap<String, i32>>,
+    }
+
+    fn value() -> Value {
+        Value {
+            name: "stat".to_string(),
+            count: -42,
+            ratio: 0.5,
+            tags: vec!["a".to_string(), "b".to_string()],
+            extra: Some(HashMap::from([("x".to_string(), 1)])),
+        }
+    }
+
+    const CODECS: [Codec; 5] = [Codec::Json, Codec::Pickle, Codec::MsgPack, Codec::Bincode, Codec::Utf8];
+
+    #[test]
+    fn struct_round_trip() {
+        for codec in [Codec::Json, Codec::Pickle, Codec::MsgPack, Codec::Bincode] {
+            let data = codec.encode(&value()).unwrap();
+            assert_eq!(codec.decode::<Value>(&data).unwrap(), value(), "{:?}", codec);
+        }
+    }
+
+    #[test]
+    fn string_round_trip() {
+        for codec in CODECS {
+            let data = codec.encode(&"héllo").unwrap();
+            assert_eq!(codec.decode::<String>(&data).unwrap(), "héllo", "{:?}", codec);
+        }
+        assert_eq!(Codec::Utf8.encode(&"héllo").unwrap(), "héllo".as_bytes());
+    }
+
+    #[test]
+    fn pickle_is_plain_pickle() {
+        // Protocol 3 header and STOP opcode, readable by `pickle.loads`
+        let data = Codec::Pickle.encode(&"text").unwrap();
+        assert_eq!(data.first(), Some(&0x80));
+        assert_eq!(data.last(), Some(&b'.'));
+    }
+
+    #[test]
+    fn utf8_rejects_other_values() {
+        let error = Codec::Utf8.encode(&value()).unwrap_err();
+        assert!(error.starts_with("Utf8 codec only supports string values"), "{}", error);
+        assert!(Codec::Utf8.encode(&42).is_err());
+        assert!(Codec::Utf8.decode::<String>(&[0xff, 0xfe]).is_err());
+    }
+
+    #[test]
+    fn decode_mismatched_data_fails() {
+        let data = Codec::Json.encode(&value()).unwrap();
+        assert!(Codec::Pickle.decode::<Value>(&data).is_err());
+        assert!(Codec::Json.decode::<Value>(b"{").is_err());
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for codec in CODECS {
+            assert_eq!(Codec::from_name(codec.name()), Some(codec));
+            // Same names as in the configuration
+            assert_eq!(serde_json::to_value(codec).unwrap(), codec.name());
+        }
+    }
+
+    #[test]
+    fn codec_from_metadata() {
+        assert_eq!(Codec::from_metadata(Some(b"pickle"), Codec::Json).unwrap(), Codec::Pickle);
+        // Values written without metadata, e.g. by the python services
+        assert_eq!(Codec::from_metadata(None, Codec::Pickle).unwrap(), Codec::Pickle);
+        assert_eq!(Codec::from_metadata(Some(b"yaml"), Codec::Json).unwrap_err(), "Unknown codec: yaml");
+    }
+}
\ No newline at end of file
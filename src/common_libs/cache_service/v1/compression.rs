@@ -0,0 +1,68 @@
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::io::{Read, Write};
+
+use crate::config::{CompressionAlgorithm, RedisCompressionConfig};
+
+pub struct Compressor {
+    config: RedisCompressionConfig,
+}
+
+impl Compressor {
+    pub fn new(config: RedisCompressionConfig) -> Self {
+        Self { config }
+    }
+
+    /// Compress `data` with the configured algorithm if it is at least `threshold_bytes` long.
+    ///
+    /// Returns the algorithm that was actually applied along with the resulting bytes.
+    pub fn compress(&self, data: Vec<u8>) -> Result<(CompressionAlgorithm, Vec<u8>), String> {
+        if data.len() < self.config.threshold_bytes {
+            return Ok((CompressionAlgorithm::None, data));
+        }
+
+        let compressed = match self.config.algorithm {
+            CompressionAlgorithm::None => return Ok((CompressionAlgorithm::None, data)),
+            CompressionAlgorithm::Gzip => {
+                let level = Compression::new(self.config.level.clamp(0, 9) as u32);
+                let mut encoder = GzEncoder::new(Vec::with_capacity(data.len() / 2), level);
+                match encoder.write_all(&data).and_then(|_| encoder.finish()) {
+                    Ok(compressed) => compressed,
+                    Err(e) => return Err(format!("Failed to gzip compress data: {}", e)),
+                }
+            },
+            CompressionAlgorithm::Zstd => {
+                match zstd::encode_all(data.as_slice(), self.config.level) {
+                    Ok(compressed) => compressed,
+                    Err(e) => return Err(format!("Failed to zstd compress data: {}", e)),
+                }
+            },
+        };
+
+        // Incompressible data is stored as is
+        if compressed.len() >= data.len() {
+            return Ok((CompressionAlgorithm::None, data));
+        }
+        Ok((self.config.algorithm, compressed))
+    }
+
+    pub fn decompress(algorithm: CompressionAlgorithm, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        match algorithm {
+            CompressionAlgorithm::None => Ok(data),
+            CompressionAlgorithm::Gzip => {
+                let mut decompressed = Vec::new();
+                match GzDecoder::new(data.as_slice()).read_to_end(&mut decompressed) {
+                    Ok(_) => Ok(decompressed),
+                    Err(e) => Err(format!("Failed to gzip decompress data: {}", e)),
+                }
+            },
+            CompressionAlgorithm::Zstd => {
+                match zstd::decode_all(data.as_slice()) {
+                    Ok(decompressed) => Ok(decompressed),
+                    Err(e) => Err(format!("Failed to zstd decompress data: {}", e)),
+                }
+            },
+        }
+    }
+}
\ No newline at end of file
@@ -1,36 +1,103 @@
+use futures_util::StreamExt;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 use std::any::Any;
-use std::collections::HashMap;
+use std::future::Future;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant};
 
-#[derive(Clone)]
-struct CachedItem {
-    data: Arc<dyn Any + Send + Sync>,
-    expires_at: SystemTime,
+use crate::config::InstanceCacheConfig;
+use crate::state::APP_STATE;
+use super::cache_store::{CacheStore, InsertOutcome, Lookup};
+use super::cache_weight::CacheWeight;
+
+const INVALIDATION_CHANNEL: &str = "instance_cache:invalidate";
+const INVALIDATION_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+// Identifies this instance so that it skips its own invalidations
+static INSTANCE_ID: Lazy<String> = Lazy::new(|| format!("{:016x}", rand::random::<u64>()));
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Invalidation {
+    Key(String),
+    Prefix(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct InvalidationMessage {
+    origin: String,
+    invalidation: Invalidation,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InstanceCacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub expirations: u64,
+    // New entries refused by TinyLFU admission or larger than a shard
+    pub rejections: u64,
 }
 
 pub struct InstanceCache {
-    cache: Mutex<HashMap<String, CachedItem>>,
+    shards: Box<[Mutex<CacheStore>]>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+    rejections: AtomicU64,
 }
 
 impl InstanceCache {
-    pub fn new() -> Self {
+    pub fn new(config: &InstanceCacheConfig) -> Self {
+        let shard_count = config.shards.max(1).next_power_of_two();
+        let shards = (0..shard_count)
+            .map(|_| Mutex::new(CacheStore::new(
+                config.max_entries.div_ceil(shard_count),
+                config.max_bytes.div_ceil(shard_count),
+                config.eviction,
+            )))
+            .collect::<Vec<_>>()
+            .into_boxed_slice();
+
         InstanceCache {
-            cache: Mutex::new(HashMap::new()),
+            shards,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            expirations: AtomicU64::new(0),
+            rejections: AtomicU64::new(0),
         }
     }
 
+    fn shard(&self, key: &str) -> &Mutex<CacheStore> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        // The shard count is a power of two
+        &self.shards[hasher.finish() as usize & (self.shards.len() - 1)]
+    }
+
     fn get_raw(&self, key: &str) -> Option<Arc<dyn Any + Send + Sync>> {
-        let mut cache = self.cache.lock().unwrap();
-        match cache.get(key) {
-            Some(cached_item) => {
-                if SystemTime::now() >= cached_item.expires_at {
-                    cache.remove(key);
-                    return None;
-                }
-                return Some(cached_item.data.clone());
-            }
-            None => return None,
+        let lookup = self.shard(key).lock().unwrap().get(key, Instant::now());
+        match lookup {
+            Lookup::Hit(data) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(data)
+            },
+            Lookup::Expired => {
+                self.expirations.fetch_add(1, Ordering::Relaxed);
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            },
+            Lookup::Miss => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            },
         }
     }
 
@@ -76,41 +143,238 @@ impl InstanceCache {
             .collect()
     }
 
+    /// Cache `data`, counting its weight towards `max_bytes`.
     pub fn set<T>(&self, key: &str, data: T, ttl_secs: u64)
+    where
+        T: CacheWeight + Any + Send + Sync + 'static
+    {
+        let size = data.weight();
+        self.set_with_size(key, data, ttl_secs, size);
+    }
+
+    /// Cache `data`, counting `size_hint` bytes towards `max_bytes`, e.g. its encoded length.
+    ///
+    /// Each entry must fit into a single shard, values larger than
+    /// `max_bytes / shards` are not cached.
+    pub fn set_with_size<T>(&self, key: &str, data: T, ttl_secs: u64, size_hint: usize)
     where
         T: Any + Send + Sync + 'static
     {
-        let item = CachedItem {
-            data: Arc::new(data),
-            expires_at: SystemTime::now() + Duration::from_secs(ttl_secs),
-        };
-        let mut cache = self.cache.lock().unwrap();
-        cache.insert(key.to_string(), item);
+        self.set_arc(key, Arc::new(data), ttl_secs, size_hint);
+    }
+
+    fn set_arc<T>(&self, key: &str, data: Arc<T>, ttl_secs: u64, size_hint: usize)
+    where
+        T: Any + Send + Sync + 'static
+    {
+        let now = Instant::now();
+        let mut shard = self.shard(key).lock().unwrap();
+        let outcome = shard.insert(
+            key,
+            data,
+            now + Duration::from_secs(ttl_secs),
+            size_hint,
+            now,
+        );
+        let shard_max_bytes = shard.max_bytes();
+        drop(shard);
+
+        if outcome.oversized {
+            tracing::warn!(
+                "Instance cache value for {} of {} bytes exceeds the per-entry limit of {} bytes, not cached",
+                key, size_hint, shard_max_bytes
+            );
+        }
+        self.record_insert(&outcome);
     }
 
+    fn record_insert(&self, outcome: &InsertOutcome) {
+        self.evictions.fetch_add(outcome.evicted, Ordering::Relaxed);
+        self.expirations.fetch_add(outcome.expired, Ordering::Relaxed);
+        if !outcome.stored {
+            self.rejections.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Get `key`, or cache the value computed by `init` on a miss.
+    ///
+    /// `init` runs without holding a lock, so concurrent callers may each compute
+    /// a value and the last one stored wins.
     #[allow(dead_code)]
+    pub fn get_or_insert_with<T, F>(&self, key: &str, ttl_secs: u64, init: F) -> Arc<T>
+    where
+        T: CacheWeight + Any + Send + Sync + 'static,
+        F: FnOnce() -> T,
+    {
+        if let Some(data) = self.get::<T>(key) {
+            return data;
+        }
+        let data = Arc::new(init());
+        let size = data.weight();
+        self.set_arc(key, data.clone(), ttl_secs, size);
+        data
+    }
+
+    /// Get `key`, or cache the value resolved by `init` on a miss. Errors are not cached.
+    #[allow(dead_code)]
+    pub async fn get_or_try_insert_with<T, E, F, Fut>(&self, key: &str, ttl_secs: u64, init: F) -> Result<Arc<T>, E>
+    where
+        T: CacheWeight + Any + Send + Sync + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if let Some(data) = self.get::<T>(key) {
+            return Ok(data);
+        }
+        let data = Arc::new(init().await?);
+        let size = data.weight();
+        self.set_arc(key, data.clone(), ttl_secs, size);
+        Ok(data)
+    }
+
     pub fn delete(&self, key: &str) {
-        let mut cache = self.cache.lock().unwrap();
-        cache.remove(key);
+        let mut shard = self.shard(key).lock().unwrap();
+        shard.remove(key);
+    }
+
+    #[allow(dead_code)]
+    pub fn delete_prefix(&self, prefix: &str) {
+        for shard in self.shards.iter() {
+            shard.lock().unwrap().remove_where(|key, _| key.starts_with(prefix));
+        }
+    }
+
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            shard.lock().unwrap().clear();
+        }
+    }
+
+    pub fn stats(&self) -> InstanceCacheStats {
+        let (entries, bytes) = self.shards.iter().fold((0, 0), |(entries, bytes), shard| {
+            let shard = shard.lock().unwrap();
+            (entries + shard.len(), bytes + shard.bytes())
+        });
+        InstanceCacheStats {
+            entries,
+            bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            expirations: self.expirations.load(Ordering::Relaxed),
+            rejections: self.rejections.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Drop expired entries periodically instead of only when they are read.
+    pub fn start_sweeper_task() {
+        tokio::spawn(async move {
+            let app_state = APP_STATE.get().unwrap();
+            let interval = Duration::from_secs(app_state.config.instance_cache.sweep_interval_secs.max(1));
+            loop {
+                tokio::time::sleep(interval).await;
+                app_state.instance_cache.clear_old_cache();
+                tracing::debug!("Instance cache stats: {:?}", app_state.instance_cache.stats());
+            }
+        });
+    }
+
+    /// Delete `key` here and on every other instance listening for invalidations.
+    #[allow(dead_code)]
+    pub async fn invalidate_everywhere(&self, key: &str) -> Result<(), String> {
+        self.delete(key);
+        Self::publish_invalidation(Invalidation::Key(key.to_string())).await
+    }
+
+    /// Delete every key starting with `prefix` here and on every other instance.
+    #[allow(dead_code)]
+    pub async fn invalidate_prefix_everywhere(&self, prefix: &str) -> Result<(), String> {
+        self.delete_prefix(prefix);
+        Self::publish_invalidation(Invalidation::Prefix(prefix.to_string())).await
+    }
+
+    async fn publish_invalidation(invalidation: Invalidation) -> Result<(), String> {
+        let message = InvalidationMessage {
+            origin: INSTANCE_ID.clone(),
+            invalidation,
+        };
+        let payload = match serde_json::to_string(&message) {
+            Ok(payload) => payload,
+            Err(e) => return Err(format!("Failed to serialize cache invalidation: {}", e)),
+        };
+
+        let app_state = APP_STATE.get().unwrap();
+        app_state.redis_client.publish(INVALIDATION_CHANNEL, payload).await?;
+        Ok(())
+    }
+
+    fn apply_invalidation(&self, payload: &[u8]) {
+        let message = match serde_json::from_slice::<InvalidationMessage>(payload) {
+            Ok(message) => message,
+            Err(e) => {
+                tracing::error!("Failed to deserialize cache invalidation - err: {}", e);
+                return;
+            }
+        };
+        if message.origin == *INSTANCE_ID {
+            return;
+        }
+
+        match message.invalidation {
+            Invalidation::Key(key) => self.delete(&key),
+            Invalidation::Prefix(prefix) => self.delete_prefix(&prefix),
+        }
+    }
+
+    /// Listen for invalidations published by other instances.
+    ///
+    /// Invalidations sent while disconnected are lost, so the whole cache is
+    /// cleared whenever the subscription is re-established.
+    pub fn start_invalidation_listener() {
+        tokio::spawn(async move {
+            let app_state = APP_STATE.get().unwrap();
+            let mut reconnecting = false;
+            loop {
+                match app_state.redis_client.subscribe(INVALIDATION_CHANNEL).await {
+                    Ok(pubsub) => {
+                        if reconnecting {
+                            app_state.instance_cache.clear();
+                        }
+                        let mut messages = pubsub.into_on_message();
+                        while let Some(message) = messages.next().await {
+                            app_state.instance_cache.apply_invalidation(message.get_payload_bytes());
+                        }
+                        tracing::warn!("Cache invalidation subscription closed, reconnecting");
+                    },
+                    Err(e) => tracing::error!("Failed to subscribe to cache invalidations - err: {}", e),
+                }
+                reconnecting = true;
+                tokio::time::sleep(INVALIDATION_RECONNECT_DELAY).await;
+            }
+        });
     }
 
     pub fn clear_old_cache(&self) {
-        let mut cache = self.cache.lock().unwrap();
-        let now = SystemTime::now();
-        cache.retain(|_, item| item.expires_at > now);
+        let now = Instant::now();
+        for shard in self.shards.iter() {
+            let expired = shard.lock().unwrap().remove_where(|_, expires_at| now >= expires_at);
+            self.expirations.fetch_add(expired, Ordering::Relaxed);
+        }
     }
 
     #[allow(dead_code)]
     pub fn get_keys(&self) -> Vec<String> {
         self.clear_old_cache();
-        let cache = self.cache.lock().unwrap();
-        cache.keys().cloned().collect::<Vec<_>>()
+        self.shards.iter()
+            .flat_map(|shard| shard.lock().unwrap().keys())
+            .collect()
     }
 
     #[allow(dead_code)]
     pub fn get_len(&self) -> usize {
         self.clear_old_cache();
-        let cache = self.cache.lock().unwrap();
-        cache.len()
+        self.shards.iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum()
     }
 }
\ No newline at end of file
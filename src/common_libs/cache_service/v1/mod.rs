@@ -1,2 +1,12 @@
+pub mod cache_key;
+pub mod cache_store;
+pub mod cache_weight;
+pub mod circuit_breaker;
+pub mod codec;
+pub mod compression;
 pub mod instance_cache;
-pub mod redis_client;
\ No newline at end of file
+pub mod redis_client;
+pub mod redis_connection;
+pub mod redis_pipeline;
+pub mod redis_stream_worker;
+pub mod tiered_cache;
\ No newline at end of file
@@ -1,30 +1,254 @@
-use deadpool_redis::{Config, Connection, Pool, redis::{AsyncCommands, self}, Runtime};
+use deadpool_redis::redis::{
+    aio::PubSub,
+    AsyncCommands,
+    self,
+    streams::{StreamClaimReply, StreamId, StreamMaxlen, StreamPendingCountReply, StreamPendingId, StreamReadOptions, StreamReadReply},
+};
 use serde::de::DeserializeOwned;
-use serde::Serialize;
-use serde_json::Value as JsonValue;
-use serde_pickle::{DeOptions, SerOptions};
-use std::any::TypeId;
-use std::collections::HashMap;
-use std::mem::{forget, transmute_copy};
+use serde::{Deserialize, Serialize};
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::pin::pin;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::{CompressionAlgorithm, RedisConfig, RedisMode};
+use super::codec::Codec;
+use super::compression::Compressor;
+use crate::state::APP_STATE;
+use super::circuit_breaker::CircuitBreakerStats;
+use super::redis_connection::{RedisConnection, RedisPool, RedisPoolStats};
+use super::redis_pipeline::RedisPipeline;
 
 const MAX_REDIS_BYTE_SIZE: usize = 2000000; // 2 MB
+const PARTITION_CLEANUP_GRACE_SECONDS: i64 = 60;
+const PARTITION_READ_ATTEMPTS: u32 = 2;
+pub const STREAM_DATA_FIELD: &str = "data";
+// Longest wait of blocking commands, which hold a pooled connection meanwhile
+pub const MAX_BLOCK_MS: u64 = 60_000;
+// Keys requested per SCAN round-trip and keys sent per UNLINK / EXPIRE batch
+const SCAN_COUNT: usize = 1000;
+const KEY_BATCH_SIZE: usize = 500;
+
+/// Metadata stored under `{key}:partitioned`.
+///
+/// Values written by older clients (and the python services) carry no generation,
+/// their partitions live at `{key}:partitioned:{i}` and are still readable.
+/// Such values also carry no codec, only `is_pickled`.
+#[derive(Debug, Serialize, Deserialize)]
+struct PartitionMeta {
+    partition_count: u64,
+    #[serde(default)]
+    is_pickled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    codec: Option<Codec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    compression: Option<CompressionAlgorithm>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    generation: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    checksum: Option<String>,
+}
+
+impl PartitionMeta {
+    fn from_slice(data: &[u8]) -> Result<Self, String> {
+        match serde_json::from_slice::<PartitionMeta>(data) {
+            Ok(meta) => Ok(meta),
+            Err(e) => Err(format!("Failed to parse partition metadata: {}", e)),
+        }
+    }
+
+    fn to_vec(&self) -> Result<Vec<u8>, String> {
+        match serde_json::to_vec(self) {
+            Ok(data) => Ok(data),
+            Err(e) => Err(format!("Failed to serialize partition metadata: {}", e)),
+        }
+    }
+
+    fn decode<RV>(&self, data: &[u8]) -> Result<RV, String>
+    where
+        RV: DeserializeOwned,
+    {
+        match (self.codec, self.is_pickled) {
+            (Some(codec), _) => codec.decode(data),
+            (None, true) => Codec::Pickle.decode(data),
+            // Unpickled values without a codec are either json or plain
+            // strings set from python code
+            (None, false) => match Codec::Json.decode(data) {
+                Ok(value) => Ok(value),
+                Err(_) => Codec::Utf8.decode(data),
+            },
+        }
+    }
+
+    fn partition_keys(&self, meta_key: &str) -> Vec<String> {
+        match self.generation {
+            Some(generation) => (0..self.partition_count)
+                .map(|i| format!("{}:{}:{}", meta_key, generation, i))
+                .collect(),
+            None => (0..self.partition_count)
+                .map(|i| format!("{}:{}", meta_key, i))
+                .collect(),
+        }
+    }
+}
+
+fn partition_checksum(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+// Whether Redis Cluster hashes `key` by a `{...}` hash tag rather than the whole key
+fn has_hash_tag(key: &str) -> bool {
+    match key.find('{') {
+        Some(start) => key[start + 1..].find('}').is_some_and(|len| len > 0),
+        None => false,
+    }
+}
+
+// Escape glob characters so that `key` only matches itself in a SCAN pattern
+fn escape_pattern(key: &str) -> String {
+    let mut escaped = String::with_capacity(key.len());
+    for c in key.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Outcome of the latest periodic PING.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RedisHealth {
+    pub healthy: bool,
+    pub latency_ms: Option<u64>,
+    pub last_error: Option<String>,
+    pub checked_at: Option<String>,
+}
 
 pub struct RedisClient {
-    pool: Pool,
+    mode: RedisMode,
+    pool: RedisPool,
+    compressor: Compressor,
+    health: Mutex<RedisHealth>,
 }
 
 impl RedisClient {
-    pub fn new(host: &str, port: u16) -> Self {
-        let cfg = Config::from_url(format!("redis://{}:{}/", host, port));
-        let pool = cfg.create_pool(Some(Runtime::Tokio1)).unwrap();
+    pub fn new(config: &RedisConfig, password: Option<String>) -> Self {
+        let pool = RedisPool::new(config, password).unwrap();
+        let compressor = Compressor::new(config.compression.clone());
+        let health = Mutex::new(RedisHealth::default());
+
+        Self { mode: config.mode, pool, compressor, health }
+    }
 
-        Self { pool }
+    pub fn start_health_check_task() {
+        tokio::spawn(async move {
+            Self::run_health_checks().await;
+        });
     }
 
-    async fn get_connection(&self) -> Result<Connection, String> {
-        match self.pool.get().await {
-            Ok(conn) => Ok(conn),
-            Err(err) => Err(format!("Failed to get redis connection: {}", err)),
+    async fn run_health_checks() {
+        let app_state = APP_STATE.get().unwrap();
+        let interval = Duration::from_secs(app_state.config.redis.pool.health_check_interval_secs.max(1));
+        loop {
+            app_state.redis_client.check_health().await;
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    async fn check_health(&self) {
+        let started = Instant::now();
+        let result = match self.get_connection().await {
+            Ok(mut conn) => match redis::cmd("PING").query_async::<String>(&mut conn).await {
+                Ok(_) => Ok(()),
+                Err(err) => Err(format!("Failed to ping redis: {}", err)),
+            },
+            Err(err) => Err(err),
+        };
+        let latency_ms = started.elapsed().as_millis() as u64;
+        let stats = self.pool_stats();
+
+        let health = match result {
+            Ok(_) => {
+                // At info, so that log-based metrics see the pool in prod
+                tracing::info!(
+                    latency_ms,
+                    pool_max_size = stats.max_size,
+                    pool_size = stats.size,
+                    pool_in_use = stats.in_use,
+                    pool_idle = stats.idle,
+                    pool_waiters = stats.waiters,
+                    "Redis health check succeeded"
+                );
+                RedisHealth {
+                    healthy: true,
+                    latency_ms: Some(latency_ms),
+                    last_error: None,
+                    checked_at: Some(chrono::Utc::now().to_rfc3339()),
+                }
+            },
+            Err(err) => {
+                tracing::warn!(
+                    pool_max_size = stats.max_size,
+                    pool_size = stats.size,
+                    pool_in_use = stats.in_use,
+                    pool_idle = stats.idle,
+                    pool_waiters = stats.waiters,
+                    "Redis health check failed - err: {}", err
+                );
+                RedisHealth {
+                    healthy: false,
+                    latency_ms: None,
+                    last_error: Some(err),
+                    checked_at: Some(chrono::Utc::now().to_rfc3339()),
+                }
+            },
+        };
+        *self.health.lock().unwrap() = health;
+    }
+
+    pub fn health(&self) -> RedisHealth {
+        self.health.lock().unwrap().clone()
+    }
+
+    pub fn pool_stats(&self) -> RedisPoolStats {
+        self.pool.stats()
+    }
+
+    pub fn circuit_breaker_stats(&self) -> CircuitBreakerStats {
+        self.pool.circuit_breaker().stats()
+    }
+
+    pub(super) async fn get_connection(&self) -> Result<RedisConnection, String> {
+        self.pool.get().await
+    }
+
+    // Reads are answered as misses while the circuit breaker is open, every read
+    // wrapper goes through it: empty values, `None`, `false` or a ttl of -2
+    async fn get_read_connection(&self) -> Result<Option<RedisConnection>, String> {
+        self.pool.try_get().await
+    }
+
+    /// Batch commands into a single round-trip, without atomicity guarantees.
+    pub fn pipeline(&self) -> RedisPipeline<'_> {
+        RedisPipeline::new(self, false)
+    }
+
+    /// Batch commands into a single round-trip, applied atomically via MULTI/EXEC.
+    pub fn transaction(&self) -> RedisPipeline<'_> {
+        RedisPipeline::new(self, true)
+    }
+
+    /// Base of the keys derived from `key` which are written together.
+    ///
+    /// In cluster mode `key` is wrapped in a hash tag, unless it has one, so that
+    /// its replica, partition and metadata keys all map to the same slot.
+    fn base_key(&self, key: &str) -> String {
+        match self.mode == RedisMode::Cluster && !has_hash_tag(key) {
+            true => format!("{{{}}}", key),
+            false => key.to_string(),
         }
     }
 
@@ -35,7 +259,10 @@ impl RedisClient {
     where
         RV: redis::FromRedisValue,
     {
-        let mut conn = self.get_connection().await?;
+        let mut conn = match self.get_read_connection().await? {
+            Some(conn) => conn,
+            None => return Ok(None),
+        };
         match conn.get(key).await {
             Ok(value) => Ok(value),
             Err(err) => Err(format!("Failed to get redis value for key {}: {}", key, err)),
@@ -49,7 +276,10 @@ impl RedisClient {
     where
         RV: redis::FromRedisValue,
     {
-        let mut conn = self.get_connection().await?;
+        let mut conn = match self.get_read_connection().await? {
+            Some(conn) => conn,
+            None => return Ok(keys.iter().map(|_| None).collect()),
+        };
         match conn.mget(keys).await {
             Ok(value) => Ok(value),
             Err(err) => Err(format!("Failed to get redis values for keys {:?}: {}", keys, err)),
@@ -65,80 +295,87 @@ impl RedisClient {
     where
         RV: redis::FromRedisValue,
     {
-        let replica_key = format!("{}:{}", key, rand::random::<u32>() % replica_count);
+        let replica_key = format!("{}:{}", self.base_key(key), rand::random::<u32>() % replica_count);
         self.get(&replica_key).await
     }
 
-    pub async fn get_partitioned<RV>(
+    async fn get_partition_meta(
         &self,
-        key: &str,
-    ) -> Result<Option<RV>, String>
-    where
-        RV: DeserializeOwned + 'static,
-    {
-        let meta_key = format!("{}:partitioned", key);
-
-        let meta_info_raw = match self.get::<Vec<u8>>(&meta_key).await? {
-            Some(data) => data,
-            None => return Err(format!("Failed to get partition metadata")),
-        };
-        let meta_info = match serde_json::from_slice::<JsonValue>(&meta_info_raw) {
-            Ok(value) => value,
-            Err(e) => return Err(format!("Failed to parse partition metadata: {}", e)),
-        };
-
-        let partition_count = meta_info.get("partition_count")
-            .and_then(|v| v.as_u64())
-            .ok_or("Missing partition_count")?;
-        let is_pickled = meta_info.get("is_pickled")
-            .and_then(|v| v.as_bool())
-            .unwrap_or(false);
+        meta_key: &str,
+    ) -> Result<Option<PartitionMeta>, String> {
+        match self.get::<Vec<u8>>(meta_key).await? {
+            Some(data) => PartitionMeta::from_slice(&data).map(Some),
+            None => Ok(None),
+        }
+    }
 
-        let partition_keys = (0..partition_count)
-            .map(|i| format!("{}:{}", &meta_key, i))
-            .collect::<Vec<_>>();
+    async fn read_partitions(
+        &self,
+        meta_key: &str,
+        meta: &PartitionMeta,
+    ) -> Result<Vec<u8>, String> {
+        let partition_keys = meta.partition_keys(meta_key);
         let partition_keys_str = partition_keys.iter().map(|s| s.as_str()).collect::<Vec<_>>();
 
         let partition_values = self.get_multi::<Vec<u8>>(partition_keys_str.as_slice()).await?;
 
-        if partition_values.len() != partition_count as usize {
-            return Err(format!("Mismatch in partition count: expected {}, got {}", partition_count, partition_values.len()));
+        if partition_values.len() != meta.partition_count as usize {
+            return Err(format!("Mismatch in partition count: expected {}, got {}", meta.partition_count, partition_values.len()));
         }
 
         let mut merged_partition_data = Vec::new();
         for part in partition_values.into_iter() {
-            if part.is_none() {
-                return Err(format!("Failed to get complete partitioned data"));
+            match part {
+                Some(part) => merged_partition_data.extend(part),
+                None => return Err("Failed to get complete partitioned data".to_string()),
             }
-            merged_partition_data.extend(part.unwrap());
         }
 
-        if is_pickled {
-            match serde_pickle::from_slice::<RV>(&merged_partition_data, DeOptions::default()) {
-                Ok(value) => Ok(Some(value)),
-                Err(e) => Err(format!("Failed to deserialize pickled partitioned data: {}", e)),
-            }
+        let checksum_matches = match &meta.checksum {
+            Some(checksum) => *checksum == partition_checksum(&merged_partition_data),
+            None => true,
+        };
+        if !checksum_matches {
+            return Err("Checksum mismatch in partitioned data".to_string());
         }
-        else {
-            // If RV = String, just UTF‑8 decode; otherwise try JSON
-            // unpickled strings are set from python code
-            if TypeId::of::<RV>() == TypeId::of::<String>() {
-                match String::from_utf8(merged_partition_data) {
-                    Ok(s) => {
-                        let rv = unsafe { transmute_copy::<String, RV>(&s) };
-                        forget(s);
-                        Ok(Some(rv))
-                    }
-                    Err(e) => Err(format!("Failed to get UTF‑8 partitioned data: {}", e)),
-                }
-            }
-            else {
-                match serde_json::from_slice::<RV>(&merged_partition_data) {
-                    Ok(value) => Ok(Some(value)),
-                    Err(e) => Err(format!("Failed to deserialize json partitioned data: {}", e)),
-                }
+
+        Ok(merged_partition_data)
+    }
+
+    pub async fn get_partitioned<RV>(
+        &self,
+        key: &str,
+    ) -> Result<Option<RV>, String>
+    where
+        RV: DeserializeOwned,
+    {
+        let meta_key = format!("{}:partitioned", self.base_key(key));
+
+        // A concurrent write may flip the metadata and expire the generation being read,
+        // in which case the read is retried against the new generation
+        let mut attempt = 1;
+        let (meta_info, merged_partition_data) = loop {
+            let meta_info = match self.get_partition_meta(&meta_key).await? {
+                Some(meta_info) => meta_info,
+                None => return Ok(None),
+            };
+
+            match self.read_partitions(&meta_key, &meta_info).await {
+                Ok(data) => break (meta_info, data),
+                Err(e) if attempt < PARTITION_READ_ATTEMPTS => {
+                    tracing::warn!("Retrying partitioned read for key {} - err: {}", key, e);
+                    attempt += 1;
+                },
+                Err(e) => return Err(e),
             }
-        }
+        };
+
+        let merged_partition_data = Compressor::decompress(
+            meta_info.compression.unwrap_or(CompressionAlgorithm::None),
+            merged_partition_data,
+        )?;
+
+        meta_info.decode(&merged_partition_data).map(Some)
     }
 
     pub async fn set<V>(
@@ -167,6 +404,75 @@ impl RedisClient {
         }
     }
 
+    /// Set `key` only if it does not exist, expiring after `expiry_ms`. Returns whether it was set.
+    pub async fn set_nx<V>(
+        &self,
+        key: &str,
+        value: V,
+        expiry_ms: u64
+    ) -> Result<bool, String>
+    where
+        V: redis::ToRedisArgs + Send + Sync,
+    {
+        let mut conn = self.get_connection().await?;
+        let options = redis::SetOptions::default()
+            .conditional_set(redis::ExistenceCheck::NX)
+            .with_expiration(redis::SetExpiry::PX(expiry_ms));
+        match conn.set_options::<_, _, Option<String>>(key, value, options).await {
+            Ok(reply) => Ok(reply.is_some()),
+            Err(err) => Err(format!("Failed to set redis value for key {}: {}", key, err)),
+        }
+    }
+
+    /// Key of the codec a value was written with by `set_typed`, in the slot of `key`.
+    fn codec_key(&self, key: &str) -> String {
+        format!("{}:codec", self.base_key(key))
+    }
+
+    /// Get a value decoded with the codec `set_typed` stored for it.
+    ///
+    /// Values without a stored codec, e.g. written by other services, are decoded with `codec`.
+    #[allow(dead_code)]
+    pub async fn get_typed<T>(
+        &self,
+        key: &str,
+        codec: Codec,
+    ) -> Result<Option<T>, String>
+    where
+        T: DeserializeOwned,
+    {
+        let codec_key = self.codec_key(key);
+        let mut values = self.get_multi::<Vec<u8>>(&[key, &codec_key]).await?.into_iter();
+        let (data, codec_name) = (values.next().flatten(), values.next().flatten());
+        match data {
+            Some(data) => Codec::from_metadata(codec_name.as_deref(), codec)?.decode(&data).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Set the encoded value at `key` and its codec at `{key}:codec`.
+    ///
+    /// The value is stored as is, e.g. readable by `pickle.loads`. Other writers
+    /// of `key` must remove the codec key.
+    #[allow(dead_code)]
+    pub async fn set_typed<T>(
+        &self,
+        key: String,
+        value: &T,
+        codec: Codec,
+        expiry_seconds: Option<u64>,
+    ) -> Result<(), String>
+    where
+        T: Serialize,
+    {
+        let data = codec.encode(value)?;
+        let codec_key = self.codec_key(&key);
+        self.set_multi(vec![(key, data), (codec_key, codec.name().as_bytes().to_vec())], expiry_seconds).await
+    }
+
+    /// Set several keys in one transaction.
+    ///
+    /// In cluster mode all keys must share a hash tag.
     pub async fn set_multi<V>(
         &self,
         key_values: Vec<(String, V)>,
@@ -175,16 +481,20 @@ impl RedisClient {
     where
         V: redis::ToRedisArgs + Send + Sync + 'static,
     {
-        let mut conn = self.get_connection().await?;
-        match conn.mset::<_, _, String>(&key_values).await {
-            Ok(_) => {
-                if let Some(expiry) = expiry_seconds {
-                    for (key, _) in &key_values {
-                        self.expire(key, expiry as i64).await?;
-                    }
+        let mut transaction = self.transaction();
+        match expiry_seconds {
+            Some(expiry) => {
+                for (key, value) in &key_values {
+                    transaction.set(key, value, Some(expiry)).ignore();
                 }
-                Ok(())
             },
+            None => {
+                transaction.mset(&key_values).ignore();
+            }
+        }
+
+        match transaction.execute::<()>().await {
+            Ok(_) => Ok(()),
             Err(err) => Err(format!("Failed to set redis value for keys {:?}: {}",
                 key_values.iter().map(|(k, _)| k).collect::<Vec<_>>(),
                 err
@@ -203,10 +513,12 @@ impl RedisClient {
     where
         V: redis::ToRedisArgs + Send + Sync + Clone + 'static,
     {
-        let key_values = (0..replica_count).map(|i| (format!("{}:{}", key, i), data.clone())).collect::<Vec<_>>();
+        let base_key = self.base_key(&key);
+        let key_values = (0..replica_count).map(|i| (format!("{}:{}", base_key, i), data.clone())).collect::<Vec<_>>();
         self.set_multi(key_values, expiry_seconds).await
     }
 
+    /// Write `data` pickled and split across partitions, readable by the python services.
     pub async fn set_partitioned<V>(
         &self,
         key: String,
@@ -216,26 +528,78 @@ impl RedisClient {
     where
         V: Serialize,
     {
-        let meta_key = format!("{}:partitioned", key);
+        self.set_partitioned_with_codec(key, &data, Codec::Pickle, expiry_seconds).await
+    }
+
+    /// Write `data` encoded with `codec` and split across partitions of at most
+    /// `MAX_REDIS_BYTE_SIZE` bytes, compressed first when it exceeds the configured
+    /// compression threshold.
+    ///
+    /// Partitions are written under a fresh generation before the metadata is flipped
+    /// to point at them, so readers never observe a mix of old and new partitions.
+    /// Partitions of the replaced generation are expired after a short grace period.
+    pub async fn set_partitioned_with_codec<V>(
+        &self,
+        key: String,
+        data: &V,
+        codec: Codec,
+        expiry_seconds: Option<u64>,
+    ) -> Result<(), String>
+    where
+        V: Serialize,
+    {
+        let meta_key = format!("{}:partitioned", self.base_key(&key));
+
+        let bytes = codec.encode(data)?;
+        let (compression, bytes) = self.compressor.compress(bytes)?;
 
-        let bytes = match serde_pickle::to_vec(&data, SerOptions::default()) {
-            Ok(bytes) => bytes,
-            Err(e) => return Err(format!("Failed to serialize data: {}", e)),
+        let meta_info = PartitionMeta {
+            partition_count: bytes.len().div_ceil(MAX_REDIS_BYTE_SIZE) as u64,
+            is_pickled: codec == Codec::Pickle,
+            codec: Some(codec),
+            compression: match compression {
+                CompressionAlgorithm::None => None,
+                algorithm => Some(algorithm),
+            },
+            generation: Some(rand::random::<u64>()),
+            checksum: Some(partition_checksum(&bytes)),
         };
 
-        let partition_count = (bytes.len() + MAX_REDIS_BYTE_SIZE - 1) / MAX_REDIS_BYTE_SIZE;
-        let mut partition_values: Vec<(String, Vec<u8>)> = Vec::with_capacity(partition_count + 1);
+        let partition_values = meta_info.partition_keys(&meta_key)
+            .into_iter()
+            .zip(bytes.chunks(MAX_REDIS_BYTE_SIZE).map(|chunk| chunk.to_vec()))
+            .collect::<Vec<_>>();
+        self.set_multi(partition_values, expiry_seconds).await?;
+
+        // Flip the metadata to the new generation, fetching the replaced one
+        let mut transaction = self.transaction();
+        transaction
+            .get(&meta_key)
+            .set(&meta_key, meta_info.to_vec()?, expiry_seconds)
+            .ignore();
+        let (old_meta_raw,) = transaction.execute::<(Option<Vec<u8>>,)>().await?;
 
-        for i in 0..partition_count {
-            let start = i * MAX_REDIS_BYTE_SIZE;
-            let end = ((i + 1) * MAX_REDIS_BYTE_SIZE).min(bytes.len());
-            partition_values.push((format!("{}:{}", meta_key, i), bytes[start..end].to_vec()));
+        let old_meta_info = match old_meta_raw.map(|data| PartitionMeta::from_slice(&data)) {
+            Some(Ok(old_meta_info)) => old_meta_info,
+            Some(Err(e)) => {
+                tracing::warn!("Could not clean up old partitions for key {} - err: {}", key, e);
+                return Ok(());
+            },
+            None => return Ok(()),
+        };
+        if old_meta_info.generation == meta_info.generation {
+            return Ok(());
         }
 
-        let meta_info = serde_json::json!({ "partition_count": partition_count, "is_pickled": true });
-        partition_values.push((meta_key, serde_json::to_vec(&meta_info).unwrap()));
+        let mut pipeline = self.pipeline();
+        for old_key in old_meta_info.partition_keys(&meta_key) {
+            pipeline.expire(&old_key, PARTITION_CLEANUP_GRACE_SECONDS).ignore();
+        }
+        if let Err(e) = pipeline.execute::<()>().await {
+            tracing::warn!("Could not clean up old partitions for key {} - err: {}", key, e);
+        }
 
-        self.set_multi(partition_values, expiry_seconds).await
+        Ok(())
     }
 
     pub async fn delete(
@@ -261,40 +625,164 @@ impl RedisClient {
         }
     }
 
+    /// Unlink keys, the memory is reclaimed in the background.
     #[allow(dead_code)]
-    pub async fn delete_replica(
+    pub async fn unlink(
         &self,
-        key: &str,
+        keys: &[&str]
     ) -> Result<u64, String> {
-        let mut i = 0;
-        loop {
-            let replica_key = format!("{}:{}", key, i);
-            match self.delete(&replica_key).await {
-                Ok(1) => i += 1,
-                Ok(_) => break,
-                Err(e) => return Err(e)
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        let mut conn = self.get_connection().await?;
+        match conn.unlink(keys).await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to unlink redis keys {:?}: {}", keys, err)),
+        }
+    }
+
+    async fn scan_page(
+        &self,
+        cursor: u64,
+        pattern: &str,
+        count: usize,
+        slot_key: Option<&str>
+    ) -> Result<(u64, Vec<String>), String> {
+        let mut conn = self.get_connection().await?;
+        let mut cmd = redis::cmd("SCAN");
+        cmd.arg(cursor).arg("MATCH").arg(pattern).arg("COUNT").arg(count);
+        let page = match slot_key {
+            Some(slot_key) => conn.query_in_slot(&cmd, slot_key).await,
+            None => cmd.query_async(&mut conn).await,
+        };
+        match page {
+            Ok(page) => Ok(page),
+            Err(err) => Err(format!("Failed to scan keys matching {}: {}", pattern, err)),
+        }
+    }
+
+    /// Stream the keys matching a glob `pattern`, fetching `count` keys per SCAN round-trip.
+    ///
+    /// A key may be yielded more than once, and keys created or deleted during the
+    /// scan may or may not be included. The stream ends after the first error.
+    pub fn scan_keys<'a>(
+        &'a self,
+        pattern: &'a str,
+        count: usize
+    ) -> impl Stream<Item = Result<String, String>> + Send + 'a {
+        self.scan_keys_in_slot(pattern, count, None)
+    }
+
+    // In cluster mode SCAN only covers one node, the one serving the slot of `slot_key` if given
+    fn scan_keys_in_slot<'a>(
+        &'a self,
+        pattern: &'a str,
+        count: usize,
+        slot_key: Option<&'a str>
+    ) -> impl Stream<Item = Result<String, String>> + Send + 'a {
+        let state = (Some(0_u64), VecDeque::<String>::new());
+        stream::try_unfold(state, move |(mut cursor, mut keys)| async move {
+            loop {
+                if let Some(key) = keys.pop_front() {
+                    return Ok(Some((key, (cursor, keys))));
+                }
+                let current = match cursor {
+                    Some(current) => current,
+                    None => return Ok(None),
+                };
+                let (next, page) = self.scan_page(current, pattern, count, slot_key).await?;
+                cursor = if next == 0 { None } else { Some(next) };
+                keys.extend(page);
+            }
+        })
+    }
+
+    /// Unlink every key matching a glob `pattern` in batches, returns the number of keys removed.
+    #[allow(dead_code)]
+    pub async fn delete_by_pattern(
+        &self,
+        pattern: &str
+    ) -> Result<u64, String> {
+        self.delete_scanned(pattern, None, |_| true).await
+    }
+
+    /// Set the ttl of every key matching a glob `pattern`, returns the number of keys updated.
+    #[allow(dead_code)]
+    pub async fn expire_by_pattern(
+        &self,
+        pattern: &str,
+        secs: i64
+    ) -> Result<u64, String> {
+        let mut batches = pin!(self.scan_keys(pattern, SCAN_COUNT).try_chunks(KEY_BATCH_SIZE));
+        let mut updated = 0;
+        while let Some(batch) = batches.next().await {
+            let keys = match batch {
+                Ok(keys) => keys,
+                Err(e) => return Err(e.1),
+            };
+            let mut pipeline = self.pipeline();
+            for key in &keys {
+                pipeline.expire(key, secs);
             }
+            let results = pipeline.execute::<Vec<bool>>().await?;
+            updated += results.into_iter().filter(|updated| *updated).count() as u64;
         }
-        Ok(i)
+        Ok(updated)
+    }
+
+    async fn delete_scanned<F>(
+        &self,
+        pattern: &str,
+        slot_key: Option<&str>,
+        filter: F
+    ) -> Result<u64, String>
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut batches = pin!(self.scan_keys_in_slot(pattern, SCAN_COUNT, slot_key).try_chunks(KEY_BATCH_SIZE));
+        let mut deleted = 0;
+        while let Some(batch) = batches.next().await {
+            let keys = match batch {
+                Ok(keys) => keys,
+                Err(e) => return Err(e.1),
+            };
+            let keys = keys.iter()
+                .map(|key| key.as_str())
+                .filter(|key| filter(key))
+                .collect::<Vec<_>>();
+            deleted += self.unlink(&keys).await?;
+        }
+        Ok(deleted)
+    }
+
+    /// Delete every replica of `key`, whatever replica count it was written with.
+    #[allow(dead_code)]
+    pub async fn delete_replica(
+        &self,
+        key: &str,
+    ) -> Result<u64, String> {
+        let base_key = self.base_key(key);
+        let prefix = format!("{}:", base_key);
+        let pattern = format!("{}*", escape_pattern(&prefix));
+        // Only `{key}:{i}`, not other keys sharing the prefix
+        self.delete_scanned(&pattern, Some(&base_key), |scanned| {
+            scanned.strip_prefix(prefix.as_str())
+                .is_some_and(|index| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()))
+        }).await
     }
 
+    /// Delete a partitioned value and the partitions of all its generations.
     #[allow(dead_code)]
     pub async fn delete_partitioned(
         &self,
         key: &str,
     ) -> Result<u64, String> {
-        let key = format!("{}:partitioned", key);
-        self.delete(&key).await?;
-        let mut i = 0;
-        loop {
-            let keys_pattern = format!("{}:{}", key, i);
-            match self.delete(&keys_pattern).await {
-                Ok(1) => i += 1,
-                Ok(_) => break,
-                Err(e) => return Err(e)
-            }
-        }
-        Ok(i + 1) // +1 for the metadata key
+        let base_key = self.base_key(key);
+        let meta_key = format!("{}:partitioned", base_key);
+        // The metadata first, so that readers no longer look for the partitions
+        let deleted = self.unlink(&[&meta_key]).await?;
+        let pattern = format!("{}:*", escape_pattern(&meta_key));
+        Ok(deleted + self.delete_scanned(&pattern, Some(&base_key), |_| true).await?)
     }
 
     #[allow(dead_code)]
@@ -302,13 +790,17 @@ impl RedisClient {
         &self,
         key: &str
     ) -> Result<i64, String> {
-        let mut conn = self.get_connection().await?;
+        let mut conn = match self.get_read_connection().await? {
+            Some(conn) => conn,
+            None => return Ok(-2),
+        };
         match conn.ttl(key).await {
             Ok(value) => Ok(value),
             Err(err) => Err(format!("Failed to get ttl for key {}: {}", key, err)),
         }
     }
 
+    #[allow(dead_code)]
     pub async fn expire(
         &self,
         key: &str,
@@ -386,7 +878,10 @@ impl RedisClient {
     where
         H: redis::FromRedisValue,
     {
-        let mut conn = self.get_connection().await?;
+        let mut conn = match self.get_read_connection().await? {
+            Some(conn) => conn,
+            None => return Ok(HashMap::new()),
+        };
         match conn.hgetall(key).await {
             Ok(value) => Ok(value),
             Err(err) => Err(format!("Failed to hgetall for key {}: {}", key, err)),
@@ -394,54 +889,106 @@ impl RedisClient {
     }
 
     #[allow(dead_code)]
-    pub async fn sadd<V>(
+    pub async fn hget<T>(
         &self,
-        set: &str,
-        vals: &[V]
-    ) -> Result<i64, String>
+        key: &str,
+        field: &str
+    ) -> Result<Option<T>, String>
     where
-        V: redis::ToRedisArgs + Send + Sync + 'static,
+        T: redis::FromRedisValue,
     {
-        let mut conn = self.get_connection().await?;
-        match conn.sadd(set, vals).await {
+        let mut conn = match self.get_read_connection().await? {
+            Some(conn) => conn,
+            None => return Ok(None),
+        };
+        match conn.hget(key, field).await {
             Ok(value) => Ok(value),
-            Err(err) => Err(format!("Failed to sadd for set {}: {}", set, err)),
+            Err(err) => Err(format!("Failed to hget for key {}.{}: {}", key, field, err)),
         }
     }
 
     #[allow(dead_code)]
-    pub async fn sismember<V>(
+    pub async fn hmget<T>(
         &self,
-        set: &str,
-        val: V
-    ) -> Result<bool, String>
+        key: &str,
+        fields: &[&str]
+    ) -> Result<Vec<Option<T>>, String>
     where
-        V: redis::ToRedisArgs + Send + Sync + 'static,
+        T: redis::FromRedisValue,
     {
+        if fields.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut conn = match self.get_read_connection().await? {
+            Some(conn) => conn,
+            None => return Ok(fields.iter().map(|_| None).collect()),
+        };
+        // Explicit HMGET, `hget` sends HGET for a single field and the reply would not be a list
+        match redis::cmd("HMGET").arg(key).arg(fields).query_async(&mut conn).await {
+            Ok(values) => Ok(values),
+            Err(err) => Err(format!("Failed to hmget for key {}: {}", key, err)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn hdel(
+        &self,
+        key: &str,
+        fields: &[&str]
+    ) -> Result<i64, String> {
         let mut conn = self.get_connection().await?;
-        match conn.sismember(set, val).await {
+        match conn.hdel(key, fields).await {
             Ok(value) => Ok(value),
-            Err(err) => Err(format!("Failed to determine sismember for set {}: {}", set, err)),
+            Err(err) => Err(format!("Failed to hdel for key {}: {}", key, err)),
         }
     }
 
     #[allow(dead_code)]
-    pub async fn smembers<T>(
+    pub async fn hexists(
         &self,
-        set: &str
-    ) -> Result<Vec<T>, String>
+        key: &str,
+        field: &str
+    ) -> Result<bool, String> {
+        let mut conn = match self.get_read_connection().await? {
+            Some(conn) => conn,
+            None => return Ok(false),
+        };
+        match conn.hexists(key, field).await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to hexists for key {}.{}: {}", key, field, err)),
+        }
+    }
+
+    /// Fetch one page of hash fields, continue with the returned cursor until it is 0.
+    #[allow(dead_code)]
+    pub async fn hscan<T>(
+        &self,
+        key: &str,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize
+    ) -> Result<(u64, Vec<(String, T)>), String>
     where
         T: redis::FromRedisValue,
     {
-        let mut conn = self.get_connection().await?;
-        match conn.smembers(set).await {
-            Ok(value) => Ok(value),
-            Err(err) => Err(format!("Failed to smembers for set {}: {}", set, err)),
+        let mut conn = match self.get_read_connection().await? {
+            Some(conn) => conn,
+            None => return Ok((0, Vec::new())),
+        };
+        let mut cmd = redis::cmd("HSCAN");
+        cmd.arg(key).arg(cursor);
+        if let Some(pattern) = pattern {
+            cmd.arg("MATCH").arg(pattern);
+        }
+        cmd.arg("COUNT").arg(count);
+        match cmd.query_async(&mut conn).await {
+            Ok(page) => Ok(page),
+            Err(err) => Err(format!("Failed to hscan for key {}: {}", key, err)),
         }
     }
 
     #[allow(dead_code)]
-    pub async fn srem<V>(
+    pub async fn sadd<V>(
         &self,
         set: &str,
         vals: &[V]
@@ -450,10 +997,678 @@ impl RedisClient {
         V: redis::ToRedisArgs + Send + Sync + 'static,
     {
         let mut conn = self.get_connection().await?;
-        match conn.srem(set, vals).await {
+        match conn.sadd(set, vals).await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to sadd for set {}: {}", set, err)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn sismember<V>(
+        &self,
+        set: &str,
+        val: V
+    ) -> Result<bool, String>
+    where
+        V: redis::ToRedisArgs + Send + Sync + 'static,
+    {
+        let mut conn = match self.get_read_connection().await? {
+            Some(conn) => conn,
+            None => return Ok(false),
+        };
+        match conn.sismember(set, val).await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to determine sismember for set {}: {}", set, err)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn smembers<T>(
+        &self,
+        set: &str
+    ) -> Result<Vec<T>, String>
+    where
+        T: redis::FromRedisValue,
+    {
+        let mut conn = match self.get_read_connection().await? {
+            Some(conn) => conn,
+            None => return Ok(Vec::new()),
+        };
+        match conn.smembers(set).await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to smembers for set {}: {}", set, err)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn srem<V>(
+        &self,
+        set: &str,
+        vals: &[V]
+    ) -> Result<i64, String>
+    where
+        V: redis::ToRedisArgs + Send + Sync + 'static,
+    {
+        let mut conn = self.get_connection().await?;
+        match conn.srem(set, vals).await {
             Ok(value) => Ok(value),
             Err(err) => Err(format!("Failed to srem from set {}: {}", set, err)),
         }
     }
 
+    #[allow(dead_code)]
+    pub async fn zadd<M>(
+        &self,
+        key: &str,
+        members: &[(f64, M)]
+    ) -> Result<i64, String>
+    where
+        M: redis::ToRedisArgs + Send + Sync + 'static,
+    {
+        let mut conn = self.get_connection().await?;
+        match conn.zadd_multiple(key, members).await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to zadd for key {}: {}", key, err)),
+        }
+    }
+
+    /// Members ranked `start..=stop`, highest score first when `reverse` is set.
+    #[allow(dead_code)]
+    pub async fn zrange<T>(
+        &self,
+        key: &str,
+        start: isize,
+        stop: isize,
+        reverse: bool
+    ) -> Result<Vec<T>, String>
+    where
+        T: redis::FromRedisValue,
+    {
+        let mut conn = match self.get_read_connection().await? {
+            Some(conn) => conn,
+            None => return Ok(Vec::new()),
+        };
+        let result = match reverse {
+            true => conn.zrevrange(key, start, stop).await,
+            false => conn.zrange(key, start, stop).await,
+        };
+        match result {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to zrange for key {}: {}", key, err)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn zrange_with_scores<T>(
+        &self,
+        key: &str,
+        start: isize,
+        stop: isize,
+        reverse: bool
+    ) -> Result<Vec<(T, f64)>, String>
+    where
+        T: redis::FromRedisValue,
+    {
+        let mut conn = match self.get_read_connection().await? {
+            Some(conn) => conn,
+            None => return Ok(Vec::new()),
+        };
+        let result = match reverse {
+            true => conn.zrevrange_withscores(key, start, stop).await,
+            false => conn.zrange_withscores(key, start, stop).await,
+        };
+        match result {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to zrange_with_scores for key {}: {}", key, err)),
+        }
+    }
+
+    /// Members scored between `min` and `max`, which accept `-inf`, `+inf` and `(` exclusive bounds.
+    #[allow(dead_code)]
+    pub async fn zrange_by_score<T, S>(
+        &self,
+        key: &str,
+        min: S,
+        max: S,
+        limit: Option<(isize, isize)>
+    ) -> Result<Vec<T>, String>
+    where
+        T: redis::FromRedisValue,
+        S: redis::ToRedisArgs + Send + Sync,
+    {
+        let mut conn = match self.get_read_connection().await? {
+            Some(conn) => conn,
+            None => return Ok(Vec::new()),
+        };
+        let result = match limit {
+            Some((offset, count)) => conn.zrangebyscore_limit(key, min, max, offset, count).await,
+            None => conn.zrangebyscore(key, min, max).await,
+        };
+        match result {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to zrange_by_score for key {}: {}", key, err)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn zrem<M>(
+        &self,
+        key: &str,
+        members: &[M]
+    ) -> Result<i64, String>
+    where
+        M: redis::ToRedisArgs + Send + Sync + 'static,
+    {
+        let mut conn = self.get_connection().await?;
+        match conn.zrem(key, members).await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to zrem for key {}: {}", key, err)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn zincr_by<M>(
+        &self,
+        key: &str,
+        member: M,
+        delta: f64
+    ) -> Result<f64, String>
+    where
+        M: redis::ToRedisArgs + Send + Sync + 'static,
+    {
+        let mut conn = self.get_connection().await?;
+        match conn.zincr(key, member, delta).await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to zincr_by for key {}: {}", key, err)),
+        }
+    }
+
+    /// Rank of `member`, counted from the highest score when `reverse` is set.
+    #[allow(dead_code)]
+    pub async fn zrank<M>(
+        &self,
+        key: &str,
+        member: M,
+        reverse: bool
+    ) -> Result<Option<i64>, String>
+    where
+        M: redis::ToRedisArgs + Send + Sync + 'static,
+    {
+        let mut conn = match self.get_read_connection().await? {
+            Some(conn) => conn,
+            None => return Ok(None),
+        };
+        let result = match reverse {
+            true => conn.zrevrank(key, member).await,
+            false => conn.zrank(key, member).await,
+        };
+        match result {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to zrank for key {}: {}", key, err)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn zscore<M>(
+        &self,
+        key: &str,
+        member: M
+    ) -> Result<Option<f64>, String>
+    where
+        M: redis::ToRedisArgs + Send + Sync + 'static,
+    {
+        let mut conn = match self.get_read_connection().await? {
+            Some(conn) => conn,
+            None => return Ok(None),
+        };
+        match conn.zscore(key, member).await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to zscore for key {}: {}", key, err)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn lpush<V>(
+        &self,
+        key: &str,
+        values: &[V]
+    ) -> Result<i64, String>
+    where
+        V: redis::ToRedisArgs + Send + Sync + 'static,
+    {
+        let mut conn = self.get_connection().await?;
+        match conn.lpush(key, values).await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to lpush for key {}: {}", key, err)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn rpush<V>(
+        &self,
+        key: &str,
+        values: &[V]
+    ) -> Result<i64, String>
+    where
+        V: redis::ToRedisArgs + Send + Sync + 'static,
+    {
+        let mut conn = self.get_connection().await?;
+        match conn.rpush(key, values).await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to rpush for key {}: {}", key, err)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn lrange<T>(
+        &self,
+        key: &str,
+        start: isize,
+        stop: isize
+    ) -> Result<Vec<T>, String>
+    where
+        T: redis::FromRedisValue,
+    {
+        let mut conn = match self.get_read_connection().await? {
+            Some(conn) => conn,
+            None => return Ok(Vec::new()),
+        };
+        match conn.lrange(key, start, stop).await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to lrange for key {}: {}", key, err)),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn ltrim(
+        &self,
+        key: &str,
+        start: isize,
+        stop: isize
+    ) -> Result<(), String> {
+        let mut conn = self.get_connection().await?;
+        match conn.ltrim::<_, ()>(key, start, stop).await {
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("Failed to ltrim for key {}: {}", key, err)),
+        }
+    }
+
+    /// Pop from the first non-empty list, waiting up to `timeout_secs`.
+    ///
+    /// The wait must be above 0, which waits forever, and at most `MAX_BLOCK_MS`.
+    #[allow(dead_code)]
+    pub async fn blpop<T>(
+        &self,
+        keys: &[&str],
+        timeout_secs: f64
+    ) -> Result<Option<(String, T)>, String>
+    where
+        T: redis::FromRedisValue,
+    {
+        let wait = Self::block_wait((timeout_secs * 1000.0).ceil() as u64)?;
+        let mut conn = self.get_connection().await?;
+        conn.extend_timeout(wait);
+        match conn.blpop(keys, timeout_secs).await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to blpop for keys {:?}: {}", keys, err)),
+        }
+    }
+
+    fn block_wait(block_ms: u64) -> Result<Duration, String> {
+        match block_ms {
+            0 => Err("Blocking forever is not supported, the command would time out".to_string()),
+            block_ms if block_ms > MAX_BLOCK_MS => Err(format!("Block of {} ms exceeds the maximum of {} ms", block_ms, MAX_BLOCK_MS)),
+            block_ms => Ok(Duration::from_millis(block_ms)),
+        }
+    }
+
+    pub async fn eval_script<A, RV>(
+        &self,
+        script: &redis::Script,
+        keys: &[String],
+        args: &[A]
+    ) -> Result<RV, String>
+    where
+        A: redis::ToRedisArgs,
+        RV: redis::FromRedisValue,
+    {
+        let mut conn = self.get_connection().await?;
+        let mut invocation = script.prepare_invoke();
+        for key in keys {
+            invocation.key(key);
+        }
+        for arg in args {
+            invocation.arg(arg);
+        }
+        match invocation.invoke_async(&mut conn).await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to eval script for keys {:?}: {}", keys, err)),
+        }
+    }
+
+    pub async fn publish<V>(
+        &self,
+        channel: &str,
+        message: V
+    ) -> Result<i64, String>
+    where
+        V: redis::ToRedisArgs + Send + Sync,
+    {
+        let mut conn = self.get_connection().await?;
+        match conn.publish(channel, message).await {
+            Ok(receivers) => Ok(receivers),
+            Err(err) => Err(format!("Failed to publish to channel {}: {}", channel, err)),
+        }
+    }
+
+    /// Subscribe to `channel` on a dedicated connection.
+    pub async fn subscribe(&self, channel: &str) -> Result<PubSub, String> {
+        let mut pubsub = self.pool.pubsub().await?;
+        match pubsub.subscribe(channel).await {
+            Ok(_) => Ok(pubsub),
+            Err(err) => Err(format!("Failed to subscribe to channel {}: {}", channel, err)),
+        }
+    }
+
+    /// Append an entry to a stream, trimming it to roughly `max_len` entries.
+    pub async fn xadd<V>(
+        &self,
+        stream: &str,
+        fields: &[(&str, V)],
+        max_len: Option<usize>
+    ) -> Result<String, String>
+    where
+        V: redis::ToRedisArgs + Send + Sync,
+    {
+        let mut conn = self.get_connection().await?;
+        let result = match max_len {
+            Some(max_len) => conn.xadd_maxlen(stream, StreamMaxlen::Approx(max_len), "*", fields).await,
+            None => conn.xadd(stream, "*", fields).await,
+        };
+        match result {
+            Ok(id) => Ok(id),
+            Err(err) => Err(format!("Failed to xadd to stream {}: {}", stream, err)),
+        }
+    }
+
+    /// Append `message` encoded with `codec`, for consumption by a `StreamWorker`.
+    #[allow(dead_code)]
+    pub async fn xadd_typed<T>(
+        &self,
+        stream: &str,
+        message: &T,
+        codec: Codec,
+        max_len: Option<usize>
+    ) -> Result<String, String>
+    where
+        T: Serialize,
+    {
+        let data = codec.encode(message)?;
+        self.xadd(stream, &[(STREAM_DATA_FIELD, data)], max_len).await
+    }
+
+    /// Create a consumer group, and the stream if missing. An existing group is left as is.
+    pub async fn xgroup_create(
+        &self,
+        stream: &str,
+        group: &str,
+        start_id: &str
+    ) -> Result<(), String> {
+        let mut conn = self.get_connection().await?;
+        match conn.xgroup_create_mkstream::<_, _, _, ()>(stream, group, start_id).await {
+            Ok(_) => Ok(()),
+            Err(err) if err.code() == Some("BUSYGROUP") => Ok(()),
+            Err(err) => Err(format!("Failed to create group {} for stream {}: {}", group, stream, err)),
+        }
+    }
+
+    /// Read entries never delivered to the group, blocking up to `block_ms` when there are none.
+    ///
+    /// `block_ms` must be above 0, which blocks forever, and at most `MAX_BLOCK_MS`.
+    pub async fn xreadgroup(
+        &self,
+        stream: &str,
+        group: &str,
+        consumer: &str,
+        count: usize,
+        block_ms: usize
+    ) -> Result<Vec<StreamId>, String> {
+        let wait = Self::block_wait(block_ms as u64)?;
+        let mut conn = self.get_connection().await?;
+        conn.extend_timeout(wait);
+        let options = StreamReadOptions::default()
+            .group(group, consumer)
+            .count(count)
+            .block(block_ms);
+        match conn.xread_options::<_, _, Option<StreamReadReply>>(&[stream], &[">"], &options).await {
+            Ok(Some(reply)) => Ok(reply.keys.into_iter().flat_map(|key| key.ids).collect()),
+            Ok(None) => Ok(Vec::new()),
+            Err(err) => Err(format!("Failed to xreadgroup from stream {}: {}", stream, err)),
+        }
+    }
+
+    pub async fn xack(
+        &self,
+        stream: &str,
+        group: &str,
+        ids: &[&str]
+    ) -> Result<i64, String> {
+        let mut conn = self.get_connection().await?;
+        match conn.xack(stream, group, ids).await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to xack {:?} on stream {}: {}", ids, stream, err)),
+        }
+    }
+
+    /// List entries pending in the group for at least `min_idle_ms`.
+    pub async fn xpending(
+        &self,
+        stream: &str,
+        group: &str,
+        min_idle_ms: u64,
+        count: usize
+    ) -> Result<Vec<StreamPendingId>, String> {
+        let mut conn = self.get_connection().await?;
+        let result = redis::cmd("XPENDING")
+            .arg(stream)
+            .arg(group)
+            .arg("IDLE")
+            .arg(min_idle_ms)
+            .arg("-")
+            .arg("+")
+            .arg(count)
+            .query_async::<StreamPendingCountReply>(&mut conn)
+            .await;
+        match result {
+            Ok(reply) => Ok(reply.ids),
+            Err(err) => Err(format!("Failed to xpending on stream {}: {}", stream, err)),
+        }
+    }
+
+    /// Transfer pending entries idle for at least `min_idle_ms` to `consumer`.
+    pub async fn xclaim(
+        &self,
+        stream: &str,
+        group: &str,
+        consumer: &str,
+        min_idle_ms: u64,
+        ids: &[&str]
+    ) -> Result<Vec<StreamId>, String> {
+        let mut conn = self.get_connection().await?;
+        match conn.xclaim::<_, _, _, _, _, StreamClaimReply>(stream, group, consumer, min_idle_ms, ids).await {
+            Ok(reply) => Ok(reply.ids),
+            Err(err) => Err(format!("Failed to xclaim {:?} on stream {}: {}", ids, stream, err)),
+        }
+    }
+
+}
+
+// Integration tests against a local Redis, ignored by default. Run them with
+// `REDIS_URL=redis://127.0.0.1:6379 cargo test -- --ignored`.
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    pub(crate) fn test_client() -> RedisClient {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL must be set for redis integration tests");
+        let mut config = AppConfig::default().redis;
+        config.mode = RedisMode::Standalone;
+        config.nodes = vec![url.trim_start_matches("redis://").trim_end_matches('/').to_string()];
+        config.tls = false;
+        config.username = String::new();
+        RedisClient::new(&config, None)
+    }
+
+    fn test_key(name: &str) -> String {
+        format!("test:redis_client:{}:{:08x}", name, rand::random::<u32>())
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local Redis, set REDIS_URL and run with --ignored"]
+    async fn sorted_set_commands() {
+        let client = test_client();
+        let key = test_key("zset");
+
+        let added = client.zadd(&key, &[(1.0, "a"), (2.0, "b"), (3.0, "c")]).await.unwrap();
+        assert_eq!(added, 3);
+
+        let members = client.zrange::<String>(&key, 0, -1, false).await.unwrap();
+        assert_eq!(members, vec!["a", "b", "c"]);
+        let members = client.zrange::<String>(&key, 0, 0, true).await.unwrap();
+        assert_eq!(members, vec!["c"]);
+
+        let members = client.zrange_by_score::<String, _>(&key, 2.0, 3.0, None).await.unwrap();
+        assert_eq!(members, vec!["b", "c"]);
+        let members = client.zrange_by_score::<String, _>(&key, "-inf", "+inf", Some((1, 1))).await.unwrap();
+        assert_eq!(members, vec!["b"]);
+
+        let score = client.zincr_by(&key, "a", 5.0).await.unwrap();
+        assert_eq!(score, 6.0);
+        assert_eq!(client.zrank(&key, "a", false).await.unwrap(), Some(2));
+        assert_eq!(client.zrank(&key, "a", true).await.unwrap(), Some(0));
+        assert_eq!(client.zrank(&key, "missing", false).await.unwrap(), None);
+
+        client.delete(&key).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local Redis, set REDIS_URL and run with --ignored"]
+    async fn list_commands() {
+        let client = test_client();
+        let key = test_key("list");
+
+        let len = client.lpush(&key, &["a", "b", "c"]).await.unwrap();
+        assert_eq!(len, 3);
+        assert_eq!(client.lrange::<String>(&key, 0, -1).await.unwrap(), vec!["c", "b", "a"]);
+
+        client.ltrim(&key, 0, 1).await.unwrap();
+        assert_eq!(client.lrange::<String>(&key, 0, -1).await.unwrap(), vec!["c", "b"]);
+
+        let popped = client.blpop::<String>(&[key.as_str()], 1.0).await.unwrap();
+        assert_eq!(popped, Some((key.clone(), "c".to_string())));
+        let popped = client.blpop::<String>(&[key.as_str()], 1.0).await.unwrap();
+        assert_eq!(popped, Some((key.clone(), "b".to_string())));
+
+        // Waits longer than the command timeout without losing the pushed value
+        let wait = 5.0;
+        let push_key = key.clone();
+        let pusher = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(2500)).await;
+            let client = test_client();
+            client.lpush(&push_key, &["late"]).await.unwrap();
+        });
+        let popped = client.blpop::<String>(&[key.as_str()], wait).await.unwrap();
+        assert_eq!(popped, Some((key.clone(), "late".to_string())));
+        pusher.await.unwrap();
+
+        assert_eq!(client.blpop::<String>(&[key.as_str()], 0.1).await.unwrap(), None);
+        assert!(client.blpop::<String>(&[key.as_str()], 0.0).await.is_err());
+
+        client.delete(&key).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local Redis, set REDIS_URL and run with --ignored"]
+    async fn hash_commands() {
+        let client = test_client();
+        let key = test_key("hash");
+
+        client.hmset(&key, &[("a", "1"), ("b", "2"), ("c", "3")]).await.unwrap();
+
+        let values = client.hmget::<String>(&key, &["a", "missing", "c"]).await.unwrap();
+        assert_eq!(values, vec![Some("1".to_string()), None, Some("3".to_string())]);
+
+        assert_eq!(client.hdel(&key, &["a", "missing"]).await.unwrap(), 1);
+        assert!(!client.hexists(&key, "a").await.unwrap());
+
+        let mut cursor = 0;
+        let mut fields = Vec::new();
+        loop {
+            let (next, page) = client.hscan::<String>(&key, cursor, None, 10).await.unwrap();
+            fields.extend(page);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        fields.sort();
+        assert_eq!(fields, vec![("b".to_string(), "2".to_string()), ("c".to_string(), "3".to_string())]);
+
+        let (_, matched) = client.hscan::<String>(&key, 0, Some("b*"), 10).await.unwrap();
+        assert_eq!(matched, vec![("b".to_string(), "2".to_string())]);
+
+        client.delete(&key).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local Redis, set REDIS_URL and run with --ignored"]
+    async fn typed_values() {
+        let client = test_client();
+        let key = test_key("typed");
+
+        client.set_typed(key.clone(), &vec![1, 2, 3], Codec::Pickle, Some(60)).await.unwrap();
+        // Stored as plain pickle, the codec is kept beside it
+        let raw = client.get::<Vec<u8>>(&key).await.unwrap().unwrap();
+        assert_eq!(Codec::Pickle.decode::<Vec<i32>>(&raw).unwrap(), vec![1, 2, 3]);
+        assert_eq!(client.get_typed::<Vec<i32>>(&key, Codec::Json).await.unwrap(), Some(vec![1, 2, 3]));
+
+        // Written without a codec, e.g. by the python services
+        client.delete(&client.codec_key(&key)).await.unwrap();
+        assert_eq!(client.get_typed::<Vec<i32>>(&key, Codec::Pickle).await.unwrap(), Some(vec![1, 2, 3]));
+        assert!(client.get_typed::<Vec<i32>>(&key, Codec::Json).await.is_err());
+
+        client.delete(&key).await.unwrap();
+        assert_eq!(client.get_typed::<Vec<i32>>(&key, Codec::Json).await.unwrap(), None);
+    }
+
+    #[test]
+    fn escape_pattern_escapes_globs() {
+        assert_eq!(escape_pattern("user:1"), "user:1");
+        assert_eq!(escape_pattern("a*b?[c]\\d"), "a\\*b\\?\\[c\\]\\\\d");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local Redis, set REDIS_URL and run with --ignored"]
+    async fn delete_replicas_and_partitions() {
+        let client = test_client();
+        let key = test_key("derived");
+        let other_key = format!("{}:other", key);
+
+        client.set(other_key.clone(), "kept", Some(60)).await.unwrap();
+        client.set_replica(key.clone(), "value", 5, Some(60)).await.unwrap();
+        // Removed without knowing the replica count
+        assert_eq!(client.delete_replica(&key).await.unwrap(), 5);
+        assert_eq!(client.get_replica::<String>(&key, 5).await.unwrap(), None);
+
+        client.set_partitioned(key.clone(), "first".repeat(1000), Some(60)).await.unwrap();
+        client.set_partitioned(key.clone(), "second".repeat(1000), None).await.unwrap();
+        assert!(client.delete_partitioned(&key).await.unwrap() >= 2);
+        assert_eq!(client.get_partitioned::<String>(&key).await.unwrap(), None);
+        let pattern = format!("{}:partitioned*", escape_pattern(&key));
+        assert_eq!(client.scan_keys(&pattern, 100).collect::<Vec<_>>().await.len(), 0);
+
+        assert_eq!(client.get::<String>(&other_key).await.unwrap().as_deref(), Some("kept"));
+        client.delete(&other_key).await.unwrap();
+    }
 }
\ No newline at end of file
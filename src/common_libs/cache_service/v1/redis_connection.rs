@@ -0,0 +1,392 @@
+use deadpool_redis::{
+    cluster,
+    ConnectionAddr,
+    ConnectionInfo,
+    PoolConfig,
+    redis::{
+        self,
+        aio::{ConnectionLike, PubSub},
+        cluster_routing::{get_slot, Route, RoutingInfo, SingleNodeRoutingInfo, SlotAddr},
+        Cmd,
+        ErrorKind,
+        FromRedisValue,
+        Pipeline,
+        RedisError,
+        RedisFuture,
+        Value,
+    },
+    PoolError,
+    RedisConnectionInfo,
+    Runtime,
+    sentinel,
+    Timeouts,
+};
+use serde::Serialize;
+use std::future::Future;
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::{RedisConfig, RedisMode};
+use super::circuit_breaker::CircuitBreaker;
+
+/// Connection pool matching the configured redis deployment.
+pub struct RedisPool {
+    pool: PoolKind,
+    pubsub_source: PubSubSource,
+    command_timeout: Duration,
+    circuit_breaker: Arc<CircuitBreaker>,
+}
+
+enum PoolKind {
+    Standalone(deadpool_redis::Pool),
+    Sentinel(sentinel::Pool),
+    Cluster(cluster::Pool),
+}
+
+// Pub/sub needs a dedicated connection outside of the pool
+enum PubSubSource {
+    // Standalone server, or any cluster node as PUBLISH is broadcast cluster-wide
+    Node(redis::ConnectionInfo),
+    Sentinel {
+        sentinels: Vec<redis::ConnectionInfo>,
+        master_name: String,
+        node_connection_info: redis::sentinel::SentinelNodeConnectionInfo,
+    },
+}
+
+enum ConnectionKind {
+    Standalone(deadpool_redis::Connection),
+    Sentinel(sentinel::Connection),
+    Cluster(cluster::Connection),
+}
+
+/// Pooled connection, usable with `AsyncCommands` regardless of the deployment.
+///
+/// Every command fails with a timeout error once `command_timeout` elapses,
+/// extended by `extend_timeout` for blocking commands. Outcomes are recorded
+/// in the pool's circuit breaker.
+pub struct RedisConnection {
+    conn: ConnectionKind,
+    command_timeout: Duration,
+    circuit_breaker: Arc<CircuitBreaker>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RedisPoolStats {
+    pub max_size: usize,
+    pub size: usize,
+    pub in_use: usize,
+    pub idle: usize,
+    pub waiters: usize,
+}
+
+impl RedisPool {
+    pub fn new(config: &RedisConfig, password: Option<String>) -> Result<Self, String> {
+        let (pool, pubsub_source) = Self::create_pool(config, password)?;
+        let command_timeout = Duration::from_millis(config.pool.command_timeout_ms);
+        let circuit_breaker = Arc::new(CircuitBreaker::new(&config.circuit_breaker));
+
+        Ok(Self { pool, pubsub_source, command_timeout, circuit_breaker })
+    }
+
+    fn pool_config(config: &RedisConfig) -> PoolConfig {
+        let mut pool_config = PoolConfig::new(config.pool.max_size);
+        pool_config.timeouts = Timeouts {
+            wait: Some(Duration::from_millis(config.pool.wait_timeout_ms)),
+            create: Some(Duration::from_millis(config.pool.create_timeout_ms)),
+            recycle: Some(Duration::from_millis(config.pool.recycle_timeout_ms)),
+        };
+        pool_config
+    }
+
+    fn create_pool(config: &RedisConfig, password: Option<String>) -> Result<(PoolKind, PubSubSource), String> {
+        let nodes = config.nodes.iter()
+            .map(|node| Self::connection_addr(config, node))
+            .collect::<Result<Vec<_>, _>>()?;
+        if nodes.is_empty() {
+            return Err("No redis nodes configured".to_string());
+        }
+
+        let redis_connection_info = RedisConnectionInfo {
+            db: config.db,
+            username: match config.username.as_str() {
+                "" => None,
+                username => Some(username.to_string()),
+            },
+            password,
+            ..Default::default()
+        };
+
+        match config.mode {
+            RedisMode::Standalone => {
+                let connection_info = ConnectionInfo {
+                    addr: nodes.into_iter().next().unwrap(),
+                    redis: redis_connection_info,
+                };
+                let pubsub_source = PubSubSource::Node(connection_info.clone().into());
+                let standalone_config = deadpool_redis::Config {
+                    url: None,
+                    connection: Some(connection_info),
+                    pool: Some(Self::pool_config(config)),
+                };
+                match standalone_config.create_pool(Some(Runtime::Tokio1)) {
+                    Ok(pool) => Ok((PoolKind::Standalone(pool), pubsub_source)),
+                    Err(e) => Err(format!("Failed to create redis pool: {}", e)),
+                }
+            },
+            RedisMode::Sentinel => {
+                // Sentinels are reached without credentials, the master with the configured ones
+                let sentinels = nodes.into_iter()
+                    .map(|addr| ConnectionInfo { addr, redis: RedisConnectionInfo::default() })
+                    .collect::<Vec<_>>();
+                let node_connection_info = sentinel::SentinelNodeConnectionInfo {
+                    tls_mode: match (config.tls, config.tls_insecure) {
+                        (false, _) => None,
+                        (true, false) => Some(sentinel::TlsMode::Secure),
+                        (true, true) => Some(sentinel::TlsMode::Insecure),
+                    },
+                    redis_connection_info: Some(redis_connection_info),
+                };
+                let pubsub_source = PubSubSource::Sentinel {
+                    sentinels: sentinels.iter().cloned().map(Into::into).collect(),
+                    master_name: config.master_name.clone(),
+                    node_connection_info: node_connection_info.clone().into(),
+                };
+                let sentinel_config = sentinel::Config {
+                    urls: None,
+                    connections: Some(sentinels),
+                    master_name: config.master_name.clone(),
+                    server_type: sentinel::SentinelServerType::Master,
+                    node_connection_info: Some(node_connection_info),
+                    pool: Some(Self::pool_config(config)),
+                };
+                match sentinel_config.create_pool(Some(Runtime::Tokio1)) {
+                    Ok(pool) => Ok((PoolKind::Sentinel(pool), pubsub_source)),
+                    Err(e) => Err(format!("Failed to create redis sentinel pool: {}", e)),
+                }
+            },
+            RedisMode::Cluster => {
+                if config.db != 0 {
+                    tracing::warn!("Redis db {} is ignored in cluster mode", config.db);
+                }
+                let seeds = nodes.into_iter()
+                    .map(|addr| ConnectionInfo {
+                        addr,
+                        redis: RedisConnectionInfo { db: 0, ..redis_connection_info.clone() },
+                    })
+                    .collect::<Vec<_>>();
+                let pubsub_source = PubSubSource::Node(seeds[0].clone().into());
+                let cluster_config = cluster::Config {
+                    urls: None,
+                    connections: Some(seeds),
+                    pool: Some(Self::pool_config(config)),
+                    read_from_replicas: false,
+                };
+                match cluster_config.create_pool(Some(Runtime::Tokio1)) {
+                    Ok(pool) => Ok((PoolKind::Cluster(pool), pubsub_source)),
+                    Err(e) => Err(format!("Failed to create redis cluster pool: {}", e)),
+                }
+            },
+        }
+    }
+
+    fn connection_addr(config: &RedisConfig, node: &str) -> Result<ConnectionAddr, String> {
+        let (host, port) = match node.rsplit_once(':') {
+            Some((host, port)) => match port.parse::<u16>() {
+                Ok(port) => (host.to_string(), port),
+                Err(e) => return Err(format!("Invalid port in redis node {}: {}", node, e)),
+            },
+            None => (node.to_string(), 6379),
+        };
+
+        if config.tls {
+            Ok(ConnectionAddr::TcpTls { host, port, insecure: config.tls_insecure })
+        }
+        else {
+            Ok(ConnectionAddr::Tcp(host, port))
+        }
+    }
+
+    pub async fn get(&self) -> Result<RedisConnection, String> {
+        match self.try_get().await? {
+            Some(conn) => Ok(conn),
+            None => Err("Failed to get redis connection: circuit breaker is open".to_string()),
+        }
+    }
+
+    /// Like `get`, but `None` instead of waiting for a connection while the circuit breaker is open.
+    pub async fn try_get(&self) -> Result<Option<RedisConnection>, String> {
+        if !self.circuit_breaker.try_acquire() {
+            return Ok(None);
+        }
+
+        let conn = match &self.pool {
+            PoolKind::Standalone(pool) => pool.get().await.map(ConnectionKind::Standalone),
+            PoolKind::Sentinel(pool) => pool.get().await.map(ConnectionKind::Sentinel),
+            PoolKind::Cluster(pool) => pool.get().await.map(ConnectionKind::Cluster),
+        };
+        match conn {
+            Ok(conn) => Ok(Some(RedisConnection {
+                conn,
+                command_timeout: self.command_timeout,
+                circuit_breaker: self.circuit_breaker.clone(),
+            })),
+            Err(err) => {
+                if self.is_unavailable(&err) {
+                    self.circuit_breaker.record_failure();
+                }
+                Err(format!("Failed to get redis connection: {}", err))
+            },
+        }
+    }
+
+    // Waiting on a pool exhausted by busy connections is not a redis failure
+    fn is_unavailable(&self, err: &PoolError) -> bool {
+        match err {
+            PoolError::Backend(_) => true,
+            PoolError::Timeout(_) => {
+                let stats = self.stats();
+                stats.size < stats.max_size
+            },
+            _ => false,
+        }
+    }
+
+    pub fn circuit_breaker(&self) -> &CircuitBreaker {
+        &self.circuit_breaker
+    }
+
+    /// Open a dedicated pub/sub connection, resolving the current master in sentinel mode.
+    pub async fn pubsub(&self) -> Result<PubSub, String> {
+        let client = match &self.pubsub_source {
+            PubSubSource::Node(connection_info) => redis::Client::open(connection_info.clone()),
+            PubSubSource::Sentinel { sentinels, master_name, node_connection_info } => {
+                match redis::sentinel::Sentinel::build(sentinels.clone()) {
+                    Ok(mut sentinel) => sentinel.async_master_for(master_name, Some(node_connection_info)).await,
+                    Err(e) => Err(e),
+                }
+            },
+        };
+        let client = match client {
+            Ok(client) => client,
+            Err(e) => return Err(format!("Failed to create redis pubsub client: {}", e)),
+        };
+
+        match tokio::time::timeout(self.command_timeout, client.get_async_pubsub()).await {
+            Ok(Ok(pubsub)) => Ok(pubsub),
+            Ok(Err(e)) => Err(format!("Failed to open redis pubsub connection: {}", e)),
+            Err(_) => Err(format!("Redis pubsub connection timed out after {:?}", self.command_timeout)),
+        }
+    }
+
+    pub fn stats(&self) -> RedisPoolStats {
+        let status = match &self.pool {
+            PoolKind::Standalone(pool) => pool.status(),
+            PoolKind::Sentinel(pool) => pool.status(),
+            PoolKind::Cluster(pool) => pool.status(),
+        };
+        RedisPoolStats {
+            max_size: status.max_size,
+            size: status.size,
+            in_use: status.size.saturating_sub(status.available),
+            idle: status.available,
+            waiters: status.waiting,
+        }
+    }
+}
+
+impl RedisConnection {
+    /// Extend the command timeout of this connection by the wait of a blocking command.
+    ///
+    /// Otherwise the command would be dropped while the server still waits, and a
+    /// value popped afterwards would be lost with the discarded reply.
+    pub fn extend_timeout(&mut self, wait: Duration) {
+        self.command_timeout += wait;
+    }
+
+    /// Run a keyless command, e.g. SCAN, on the node serving the slot of `slot_key`.
+    ///
+    /// Outside of cluster mode a single node serves every key.
+    pub async fn query_in_slot<T: FromRedisValue>(&mut self, cmd: &Cmd, slot_key: &str) -> Result<T, RedisError> {
+        let value = match &mut self.conn {
+            ConnectionKind::Cluster(conn) => {
+                let route = Route::new(get_slot(slot_key.as_bytes()), SlotAddr::Master);
+                let routing = RoutingInfo::SingleNode(SingleNodeRoutingInfo::SpecificNode(route));
+                Self::timeout_future(self.command_timeout, self.circuit_breaker.clone(), conn.route_command(cmd, routing)).await?
+            },
+            _ => cmd.query_async::<Value>(self).await?,
+        };
+        T::from_redis_value(&value)
+    }
+
+    fn timeout_future<'a, T>(
+        command_timeout: Duration,
+        circuit_breaker: Arc<CircuitBreaker>,
+        future: impl Future<Output = Result<T, RedisError>> + Send + 'a,
+    ) -> RedisFuture<'a, T>
+    where
+        T: Send + 'a,
+    {
+        Box::pin(async move {
+            let result = match tokio::time::timeout(command_timeout, future).await {
+                Ok(result) => result,
+                Err(_) => Err(RedisError::from(IoError::new(
+                    IoErrorKind::TimedOut,
+                    format!("Redis command timed out after {:?}", command_timeout),
+                ))),
+            };
+            match &result {
+                Err(err) if Self::is_unavailable(err) => circuit_breaker.record_failure(),
+                // Error replies still prove that redis is reachable
+                _ => circuit_breaker.record_success(),
+            }
+            result
+        })
+    }
+
+    fn is_unavailable(err: &RedisError) -> bool {
+        err.is_io_error()
+            || err.is_timeout()
+            || err.is_connection_dropped()
+            || err.is_connection_refusal()
+            || matches!(err.kind(), ErrorKind::BusyLoadingError | ErrorKind::ClusterDown | ErrorKind::MasterDown)
+    }
+}
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a Cmd) -> RedisFuture<'a, Value> {
+        let command_timeout = self.command_timeout;
+        let circuit_breaker = self.circuit_breaker.clone();
+        let future = match &mut self.conn {
+            ConnectionKind::Standalone(conn) => conn.req_packed_command(cmd),
+            ConnectionKind::Sentinel(conn) => conn.req_packed_command(cmd),
+            ConnectionKind::Cluster(conn) => conn.req_packed_command(cmd),
+        };
+        Self::timeout_future(command_timeout, circuit_breaker, future)
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        let command_timeout = self.command_timeout;
+        let circuit_breaker = self.circuit_breaker.clone();
+        let future = match &mut self.conn {
+            ConnectionKind::Standalone(conn) => conn.req_packed_commands(cmd, offset, count),
+            ConnectionKind::Sentinel(conn) => conn.req_packed_commands(cmd, offset, count),
+            ConnectionKind::Cluster(conn) => conn.req_packed_commands(cmd, offset, count),
+        };
+        Self::timeout_future(command_timeout, circuit_breaker, future)
+    }
+
+    fn get_db(&self) -> i64 {
+        match &self.conn {
+            ConnectionKind::Standalone(conn) => conn.get_db(),
+            ConnectionKind::Sentinel(conn) => conn.get_db(),
+            ConnectionKind::Cluster(conn) => conn.get_db(),
+        }
+    }
+}
\ No newline at end of file
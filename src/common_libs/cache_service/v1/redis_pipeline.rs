@@ -0,0 +1,117 @@
+use deadpool_redis::redis;
+
+use super::redis_client::RedisClient;
+
+/// Batches redis commands into a single round-trip.
+///
+/// Created via `RedisClient::pipeline` or `RedisClient::transaction`, the latter
+/// wrapping the batch in MULTI/EXEC so it is applied atomically.
+pub struct RedisPipeline<'a> {
+    client: &'a RedisClient,
+    pipe: redis::Pipeline,
+    command_count: usize,
+}
+
+impl<'a> RedisPipeline<'a> {
+    pub(super) fn new(client: &'a RedisClient, atomic: bool) -> Self {
+        let mut pipe = redis::pipe();
+        if atomic {
+            pipe.atomic();
+        }
+        Self { client, pipe, command_count: 0 }
+    }
+
+    /// Number of commands queued so far.
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.command_count
+    }
+
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.command_count == 0
+    }
+
+    /// Start an arbitrary command, arguments are added with `arg`.
+    #[allow(dead_code)]
+    pub fn cmd(&mut self, name: &str) -> &mut Self {
+        self.pipe.cmd(name);
+        self.command_count += 1;
+        self
+    }
+
+    /// Add an argument to the last queued command.
+    #[allow(dead_code)]
+    pub fn arg<A>(&mut self, arg: A) -> &mut Self
+    where
+        A: redis::ToRedisArgs,
+    {
+        self.pipe.arg(arg);
+        self
+    }
+
+    /// Drop the result of the last queued command from the returned values.
+    pub fn ignore(&mut self) -> &mut Self {
+        self.pipe.ignore();
+        self
+    }
+
+    pub fn get(&mut self, key: &str) -> &mut Self {
+        self.pipe.get(key);
+        self.command_count += 1;
+        self
+    }
+
+    pub fn set<V>(&mut self, key: &str, value: V, expiry_seconds: Option<u64>) -> &mut Self
+    where
+        V: redis::ToRedisArgs,
+    {
+        match expiry_seconds {
+            Some(expiry) => self.pipe.set_ex(key, value, expiry),
+            None => self.pipe.set(key, value),
+        };
+        self.command_count += 1;
+        self
+    }
+
+    pub fn mset<V>(&mut self, key_values: &[(String, V)]) -> &mut Self
+    where
+        V: redis::ToRedisArgs,
+    {
+        self.pipe.mset(key_values);
+        self.command_count += 1;
+        self
+    }
+
+    pub fn expire(&mut self, key: &str, secs: i64) -> &mut Self {
+        self.pipe.expire(key, secs);
+        self.command_count += 1;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn delete(&mut self, key: &str) -> &mut Self {
+        self.pipe.del(key);
+        self.command_count += 1;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn incr_by(&mut self, key: &str, by: isize) -> &mut Self {
+        self.pipe.incr(key, by);
+        self.command_count += 1;
+        self
+    }
+
+    /// Send the queued commands and collect the results of the non-ignored ones.
+    pub async fn execute<RV>(&self) -> Result<RV, String>
+    where
+        RV: redis::FromRedisValue,
+    {
+        let mut conn = self.client.get_connection().await?;
+        match self.pipe.query_async(&mut conn).await {
+            Ok(value) => Ok(value),
+            Err(err) => Err(format!("Failed to execute redis pipeline of {} commands: {}", self.command_count, err)),
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,230 @@
+use async_trait::async_trait;
+use deadpool_redis::redis::streams::StreamId;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+use crate::state::APP_STATE;
+use super::codec::Codec;
+use super::redis_client::{RedisClient, MAX_BLOCK_MS, STREAM_DATA_FIELD};
+
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+#[async_trait]
+pub trait StreamHandler: Send + Sync + 'static {
+    type Message: DeserializeOwned + Send;
+
+    /// Process one message. On error the message stays pending and is redelivered
+    /// once it has been idle for the worker's reclaim period.
+    async fn handle(&self, id: &str, message: Self::Message) -> Result<(), String>;
+}
+
+/// Consumer-group worker for a redis stream written with `RedisClient::xadd_typed`.
+///
+/// Stale pending entries, including those of crashed consumers, are reclaimed and
+/// retried. Entries delivered more than `max_deliveries` times, or which cannot be
+/// decoded, are moved to the dead-letter stream.
+pub struct StreamWorker<H: StreamHandler> {
+    stream: String,
+    group: String,
+    consumer: String,
+    handler: H,
+    codec: Codec,
+    batch_size: usize,
+    block_ms: usize,
+    reclaim_idle_ms: u64,
+    max_deliveries: usize,
+    dead_letter_stream: String,
+}
+
+#[allow(dead_code)]
+impl<H: StreamHandler> StreamWorker<H> {
+    pub fn new(stream: &str, group: &str, handler: H) -> Self {
+        Self {
+            stream: stream.to_string(),
+            group: group.to_string(),
+            consumer: format!("consumer-{:08x}", rand::random::<u32>()),
+            handler,
+            codec: Codec::Json,
+            batch_size: 10,
+            block_ms: 1000,
+            reclaim_idle_ms: 60000,
+            max_deliveries: 5,
+            dead_letter_stream: format!("{}:dead_letter", stream),
+        }
+    }
+
+    /// Set the consumer name, unique per instance by default.
+    pub fn consumer(mut self, consumer: &str) -> Self {
+        self.consumer = consumer.to_string();
+        self
+    }
+
+    /// Set the codec the messages were written with.
+    ///
+    /// Defaults to [`Codec::Json`].
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Set the maximum number of entries read or reclaimed at once.
+    ///
+    /// Defaults to 10.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Set how long a read waits for new entries, between 1 and `MAX_BLOCK_MS`.
+    ///
+    /// Defaults to 1000 ms.
+    pub fn block_ms(mut self, block_ms: usize) -> Self {
+        self.block_ms = block_ms.clamp(1, MAX_BLOCK_MS as usize);
+        self
+    }
+
+    /// Set how long an entry stays pending before it is reclaimed and retried.
+    ///
+    /// Defaults to 60000 ms.
+    pub fn reclaim_idle_ms(mut self, reclaim_idle_ms: u64) -> Self {
+        self.reclaim_idle_ms = reclaim_idle_ms;
+        self
+    }
+
+    /// Set the number of deliveries after which an entry is dead-lettered.
+    ///
+    /// Defaults to 5.
+    pub fn max_deliveries(mut self, max_deliveries: usize) -> Self {
+        self.max_deliveries = max_deliveries;
+        self
+    }
+
+    /// Set the stream receiving dead-lettered entries.
+    ///
+    /// Defaults to `{stream}:dead_letter`.
+    pub fn dead_letter_stream(mut self, dead_letter_stream: &str) -> Self {
+        self.dead_letter_stream = dead_letter_stream.to_string();
+        self
+    }
+
+    pub fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            self.run().await;
+        })
+    }
+
+    async fn run(self) {
+        let redis_client = &APP_STATE.get().unwrap().redis_client;
+
+        while let Err(e) = redis_client.xgroup_create(&self.stream, &self.group, "0").await {
+            tracing::error!("Could not create stream consumer group - err: {}", e);
+            tokio::time::sleep(ERROR_BACKOFF).await;
+        }
+
+        let reclaim_interval = Duration::from_millis((self.reclaim_idle_ms / 2).max(1000));
+        let mut last_reclaim: Option<Instant> = None;
+        loop {
+            if last_reclaim.is_none_or(|at| at.elapsed() >= reclaim_interval) {
+                self.reclaim_stale(redis_client).await;
+                last_reclaim = Some(Instant::now());
+            }
+
+            match redis_client.xreadgroup(&self.stream, &self.group, &self.consumer, self.batch_size, self.block_ms).await {
+                Ok(entries) => {
+                    for entry in entries {
+                        self.process(redis_client, entry, 1).await;
+                    }
+                },
+                Err(e) => {
+                    tracing::error!("Could not read from stream {} - err: {}", self.stream, e);
+                    tokio::time::sleep(ERROR_BACKOFF).await;
+                }
+            }
+        }
+    }
+
+    async fn reclaim_stale(&self, redis_client: &RedisClient) {
+        let pending = match redis_client.xpending(&self.stream, &self.group, self.reclaim_idle_ms, self.batch_size).await {
+            Ok(pending) => pending,
+            Err(e) => {
+                tracing::error!("Could not list pending stream entries - err: {}", e);
+                return;
+            }
+        };
+        if pending.is_empty() {
+            return;
+        }
+
+        let deliveries = pending.iter()
+            .map(|entry| (entry.id.clone(), entry.times_delivered))
+            .collect::<HashMap<_, _>>();
+        let ids = pending.iter().map(|entry| entry.id.as_str()).collect::<Vec<_>>();
+
+        let claimed = match redis_client.xclaim(&self.stream, &self.group, &self.consumer, self.reclaim_idle_ms, &ids).await {
+            Ok(claimed) => claimed,
+            Err(e) => {
+                tracing::error!("Could not claim pending stream entries - err: {}", e);
+                return;
+            }
+        };
+
+        for entry in claimed {
+            // Claiming counts as one more delivery
+            let times_delivered = deliveries.get(&entry.id).copied().unwrap_or_default() + 1;
+            self.process(redis_client, entry, times_delivered).await;
+        }
+    }
+
+    async fn process(&self, redis_client: &RedisClient, entry: StreamId, times_delivered: usize) {
+        if times_delivered > self.max_deliveries {
+            let reason = format!("Delivered {} times", times_delivered);
+            self.dead_letter(redis_client, &entry, &reason).await;
+            return;
+        }
+
+        let message = match entry.get::<Vec<u8>>(STREAM_DATA_FIELD) {
+            Some(data) => self.codec.decode::<H::Message>(&data),
+            None => Err(format!("Missing `{}` field", STREAM_DATA_FIELD)),
+        };
+        let message = match message {
+            Ok(message) => message,
+            Err(e) => {
+                self.dead_letter(redis_client, &entry, &e).await;
+                return;
+            }
+        };
+
+        match self.handler.handle(&entry.id, message).await {
+            Ok(_) => {
+                if let Err(e) = redis_client.xack(&self.stream, &self.group, &[entry.id.as_str()]).await {
+                    tracing::error!("Could not ack stream entry {} - err: {}", entry.id, e);
+                }
+            },
+            Err(e) => {
+                tracing::warn!("Stream entry {} failed, left pending for redelivery - err: {}", entry.id, e);
+            }
+        }
+    }
+
+    async fn dead_letter(&self, redis_client: &RedisClient, entry: &StreamId, reason: &str) {
+        tracing::error!("Dead-lettering stream entry {} from {} - reason: {}", entry.id, self.stream, reason);
+
+        let fields = [
+            (STREAM_DATA_FIELD, entry.get::<Vec<u8>>(STREAM_DATA_FIELD).unwrap_or_default()),
+            ("source_stream", self.stream.clone().into_bytes()),
+            ("source_id", entry.id.clone().into_bytes()),
+            ("reason", reason.as_bytes().to_vec()),
+        ];
+        if let Err(e) = redis_client.xadd(&self.dead_letter_stream, &fields, None).await {
+            // Left pending, the entry is retried on the next reclaim
+            tracing::error!("Could not dead-letter stream entry {} - err: {}", entry.id, e);
+            return;
+        }
+
+        if let Err(e) = redis_client.xack(&self.stream, &self.group, &[entry.id.as_str()]).await {
+            tracing::error!("Could not ack dead-lettered stream entry {} - err: {}", entry.id, e);
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,460 @@
+use deadpool_redis::redis::Script;
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::state::APP_STATE;
+use super::codec::Codec;
+
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// KEYS[1] = lock key, ARGV[1] = token of the holder
+pub(crate) static RELEASE_LOCK_SCRIPT: Lazy<Script> = Lazy::new(|| Script::new(r"
+    if redis.call('GET', KEYS[1]) == ARGV[1] then
+        return redis.call('DEL', KEYS[1])
+    end
+    return 0
+"));
+
+#[derive(Debug, Clone)]
+pub struct CacheOptions {
+    local_ttl_secs: u64,
+    redis_ttl_secs: u64,
+    stale_secs: u64,
+    early_refresh_beta: f64,
+    lock_ms: Option<u64>,
+    codec: Codec,
+}
+
+#[allow(dead_code)]
+impl CacheOptions {
+    /// Values are kept `local_ttl_secs` in the instance cache and are fresh for `redis_ttl_secs` in Redis.
+    ///
+    /// Instance cache entries never outlive the Redis ones.
+    pub fn new(local_ttl_secs: u64, redis_ttl_secs: u64) -> Self {
+        Self {
+            local_ttl_secs,
+            redis_ttl_secs,
+            stale_secs: 0,
+            early_refresh_beta: 1.0,
+            lock_ms: None,
+            codec: Codec::Json,
+        }
+    }
+
+    /// Serve expired values for up to `stale_secs` while they are reloaded in the background.
+    ///
+    /// Defaults to 0.
+    pub fn stale_secs(mut self, stale_secs: u64) -> Self {
+        self.stale_secs = stale_secs;
+        self
+    }
+
+    /// Scale the probabilistic early refresh, higher values refresh earlier and 0 disables it.
+    ///
+    /// Defaults to 1.0.
+    pub fn early_refresh_beta(mut self, early_refresh_beta: f64) -> Self {
+        self.early_refresh_beta = early_refresh_beta;
+        self
+    }
+
+    /// Hold a Redis lock for up to `lock_ms` while loading, so that only one instance hits the origin.
+    ///
+    /// Disabled by default.
+    pub fn redis_lock(mut self, lock_ms: u64) -> Self {
+        self.lock_ms = Some(lock_ms);
+        self
+    }
+
+    /// Set the codec of the values stored in Redis, which must support structs.
+    ///
+    /// Defaults to [`Codec::Json`].
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    // Redis keeps entries through the stale period
+    fn retention_secs(&self) -> u64 {
+        self.redis_ttl_secs + self.stale_secs
+    }
+
+    fn local_ttl_secs(&self, entry_expires_at_ms: u64, now_ms: u64) -> u64 {
+        let remaining_secs = entry_expires_at_ms.saturating_sub(now_ms).div_ceil(1000);
+        self.local_ttl_secs.min(remaining_secs)
+    }
+}
+
+/// Tier that served a cached value.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheTier {
+    Local,
+    Redis,
+    Origin,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TieredCacheStats {
+    pub local_hits: u64,
+    pub redis_hits: u64,
+    pub origin_loads: u64,
+    pub misses: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry<T> {
+    value: T,
+    fresh_until_ms: u64,
+    // How long the last load took, used to spread early refreshes
+    delta_ms: u64,
+}
+
+impl<T> CacheEntry<T> {
+    fn is_stale(&self, now_ms: u64) -> bool {
+        now_ms >= self.fresh_until_ms
+    }
+
+    // XFetch: refresh early with a probability rising as expiry nears and with the load time
+    fn should_refresh_early(&self, now_ms: u64, beta: f64) -> bool {
+        if beta <= 0.0 {
+            return false;
+        }
+        let random = rand::random::<f64>().max(f64::MIN_POSITIVE);
+        let head_start = self.delta_ms as f64 * beta * -random.ln();
+        now_ms as f64 + head_start >= self.fresh_until_ms as f64
+    }
+}
+
+enum LoadOutcome<T> {
+    Loaded(T),
+    // Another instance holds the Redis lock
+    Locked,
+}
+
+/// Cache-aside access through the instance cache (L1), Redis (L2) and the origin.
+///
+/// Writes go through both tiers, values are encoded for Redis with the
+/// configured codec. Concurrent loads of a key are coalesced within the
+/// instance and, with `CacheOptions::redis_lock`, across instances.
+pub struct TieredCache {
+    in_flight: Mutex<HashMap<String, Arc<AsyncMutex<()>>>>,
+    local_hits: AtomicU64,
+    redis_hits: AtomicU64,
+    origin_loads: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl TieredCache {
+    pub fn new() -> Self {
+        TieredCache {
+            in_flight: Mutex::new(HashMap::new()),
+            local_hits: AtomicU64::new(0),
+            redis_hits: AtomicU64::new(0),
+            origin_loads: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> TieredCacheStats {
+        TieredCacheStats {
+            local_hits: self.local_hits.load(Ordering::Relaxed),
+            redis_hits: self.redis_hits.load(Ordering::Relaxed),
+            origin_loads: self.origin_loads.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub async fn get<T>(&self, key: &str, options: &CacheOptions) -> Option<T>
+    where
+        T: DeserializeOwned + Clone + Send + Sync + 'static,
+    {
+        match self.lookup::<T>(key, options).await {
+            Some(entry) => Some(entry.value.clone()),
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            },
+        }
+    }
+
+    /// Write `value` through both tiers.
+    #[allow(dead_code)]
+    pub async fn set<T>(&self, key: &str, value: T, options: &CacheOptions) -> Result<(), String>
+    where
+        T: Serialize + Send + Sync + 'static,
+    {
+        self.store(key, options, value, 0).await
+    }
+
+    /// Delete `key` from Redis and from the instance cache of every instance.
+    pub async fn delete(&self, key: &str) -> Result<(), String> {
+        let app_state = APP_STATE.get().unwrap();
+        app_state.redis_client.delete(key).await?;
+        app_state.instance_cache.invalidate_everywhere(key).await
+    }
+
+    /// Get `key` from the instance cache or Redis, calling `loader` on a miss.
+    ///
+    /// Stale values, and fresh ones picked for early refresh, are returned
+    /// immediately while `loader` runs in the background.
+    pub async fn get_or_load<T, F, Fut>(
+        &self,
+        key: &str,
+        options: &CacheOptions,
+        loader: F,
+    ) -> Result<T, String>
+    where
+        T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, String>> + Send + 'static,
+    {
+        if let Some(entry) = self.lookup::<T>(key, options).await {
+            let now_ms = now_ms();
+            if entry.is_stale(now_ms) || entry.should_refresh_early(now_ms, options.early_refresh_beta) {
+                self.refresh_in_background(key, options, loader);
+            }
+            return Ok(entry.value.clone());
+        }
+
+        let key_lock = self.key_lock(key);
+        let result = {
+            let _guard = key_lock.lock().await;
+            // The load may have completed while waiting for the lock
+            match self.lookup::<T>(key, options).await {
+                Some(entry) => Ok(entry.value.clone()),
+                None => self.load(key, options, loader, true).await.and_then(|outcome| match outcome {
+                    LoadOutcome::Loaded(value) => Ok(value),
+                    LoadOutcome::Locked => Err(format!("Failed to load {}: locked by another instance", key)),
+                }),
+            }
+        };
+        self.release_key_lock(key, key_lock);
+        result
+    }
+
+    fn key_lock(&self, key: &str) -> Arc<AsyncMutex<()>> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        in_flight.entry(key.to_string()).or_default().clone()
+    }
+
+    fn release_key_lock(&self, key: &str, key_lock: Arc<AsyncMutex<()>>) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        // Only the map and this handle are left, nobody else is waiting
+        if Arc::strong_count(&key_lock) <= 2 {
+            in_flight.remove(key);
+        }
+    }
+
+    fn refresh_in_background<T, F, Fut>(&self, key: &str, options: &CacheOptions, loader: F)
+    where
+        T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T, String>> + Send + 'static,
+    {
+        let key_lock = self.key_lock(key);
+        let guard = match key_lock.clone().try_lock_owned() {
+            Ok(guard) => guard,
+            Err(_) => {
+                // Already being loaded in this instance
+                self.release_key_lock(key, key_lock);
+                return;
+            }
+        };
+
+        let key = key.to_string();
+        let options = options.clone();
+        tokio::spawn(async move {
+            let tiered_cache = &APP_STATE.get().unwrap().tiered_cache;
+            if let Err(e) = tiered_cache.load(&key, &options, loader, false).await {
+                tracing::error!("Failed to refresh cached value for {} - err: {}", key, e);
+            }
+            drop(guard);
+            tiered_cache.release_key_lock(&key, key_lock);
+        });
+    }
+
+    async fn lookup<T>(&self, key: &str, options: &CacheOptions) -> Option<Arc<CacheEntry<T>>>
+    where
+        T: DeserializeOwned + Clone + Send + Sync + 'static,
+    {
+        let app_state = APP_STATE.get().unwrap();
+
+        if let Some(entry) = app_state.instance_cache.get::<CacheEntry<T>>(key) {
+            self.record_hit(key, CacheTier::Local);
+            return Some(entry);
+        }
+
+        let (entry, size) = self.read_redis::<T>(key, options).await?;
+        let expires_at_ms = entry.fresh_until_ms + options.stale_secs * 1000;
+        let local_ttl_secs = options.local_ttl_secs(expires_at_ms, now_ms());
+        if local_ttl_secs > 0 {
+            app_state.instance_cache.set_with_size(key, entry.clone(), local_ttl_secs, size);
+        }
+        self.record_hit(key, CacheTier::Redis);
+        Some(Arc::new(entry))
+    }
+
+    fn record_hit(&self, key: &str, tier: CacheTier) {
+        let counter = match tier {
+            CacheTier::Local => &self.local_hits,
+            CacheTier::Redis => &self.redis_hits,
+            CacheTier::Origin => &self.origin_loads,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        tracing::trace!("Cached value for {} served from {:?}", key, tier);
+    }
+
+    // Also returns the encoded size, used as the instance cache size hint
+    async fn read_redis<T>(&self, key: &str, options: &CacheOptions) -> Option<(CacheEntry<T>, usize)>
+    where
+        T: DeserializeOwned,
+    {
+        let app_state = APP_STATE.get().unwrap();
+        let data = match app_state.redis_client.get::<Vec<u8>>(key).await {
+            Ok(Some(data)) => data,
+            Ok(None) => return None,
+            Err(e) => {
+                tracing::error!("Failed to read cached value for {} from Redis - err: {}", key, e);
+                return None;
+            }
+        };
+        match options.codec.decode::<CacheEntry<T>>(&data) {
+            Ok(entry) => Some((entry, data.len())),
+            Err(e) => {
+                // Treated as a miss, the next load overwrites it
+                tracing::warn!("Failed to decode cached value for {} - err: {}", key, e);
+                None
+            }
+        }
+    }
+
+    async fn load<T, F, Fut>(
+        &self,
+        key: &str,
+        options: &CacheOptions,
+        loader: F,
+        wait_for_lock: bool,
+    ) -> Result<LoadOutcome<T>, String>
+    where
+        T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, String>>,
+    {
+        let app_state = APP_STATE.get().unwrap();
+        let lock_key = format!("{}:lock", key);
+        let lock_token = format!("{:016x}", rand::random::<u64>());
+
+        let locked = match options.lock_ms {
+            Some(lock_ms) => match app_state.redis_client.set_nx(&lock_key, lock_token.as_str(), lock_ms).await {
+                Ok(true) => true,
+                Ok(false) => {
+                    if !wait_for_lock {
+                        return Ok(LoadOutcome::Locked);
+                    }
+                    if let Some(value) = self.wait_for_redis::<T>(key, options, lock_ms).await {
+                        return Ok(LoadOutcome::Loaded(value));
+                    }
+                    // The holder did not finish in time, load without the lock
+                    false
+                },
+                Err(e) => {
+                    tracing::error!("Failed to acquire cache load lock for {} - err: {}", key, e);
+                    false
+                },
+            },
+            None => false,
+        };
+
+        let started = Instant::now();
+        let result = loader().await;
+        let delta_ms = started.elapsed().as_millis() as u64;
+        self.record_hit(key, CacheTier::Origin);
+
+        let stored = match &result {
+            Ok(value) => self.store(key, options, value.clone(), delta_ms).await,
+            Err(_) => Ok(()),
+        };
+        if let Err(e) = stored {
+            tracing::error!("Failed to store loaded value - err: {}", e);
+        }
+
+        if locked {
+            Self::release_redis_lock(lock_key, &lock_token).await;
+        }
+
+        result.map(LoadOutcome::Loaded)
+    }
+
+    async fn release_redis_lock(lock_key: String, lock_token: &str) {
+        let app_state = APP_STATE.get().unwrap();
+        // Only the holder may release, the lock may have expired and been taken over
+        if let Err(e) = app_state.redis_client.eval_script::<&str, i64>(
+            &RELEASE_LOCK_SCRIPT,
+            std::slice::from_ref(&lock_key),
+            &[lock_token],
+        ).await {
+            tracing::error!("Failed to release cache load lock {} - err: {}", lock_key, e);
+        }
+    }
+
+    async fn wait_for_redis<T>(&self, key: &str, options: &CacheOptions, lock_ms: u64) -> Option<T>
+    where
+        T: DeserializeOwned + Send + Sync + 'static,
+    {
+        let deadline = Instant::now() + Duration::from_millis(lock_ms);
+        while Instant::now() < deadline {
+            tokio::time::sleep(LOCK_POLL_INTERVAL).await;
+            match self.read_redis::<T>(key, options).await {
+                Some((entry, _)) if !entry.is_stale(now_ms()) => return Some(entry.value),
+                _ => {},
+            }
+        }
+        None
+    }
+
+    // The instance cache is written even if Redis fails
+    async fn store<T>(&self, key: &str, options: &CacheOptions, value: T, delta_ms: u64) -> Result<(), String>
+    where
+        T: Serialize + Send + Sync + 'static,
+    {
+        let app_state = APP_STATE.get().unwrap();
+        let now_ms = now_ms();
+        let entry = CacheEntry {
+            value,
+            fresh_until_ms: now_ms + options.redis_ttl_secs * 1000,
+            delta_ms,
+        };
+        let local_ttl_secs = options.local_ttl_secs(entry.fresh_until_ms + options.stale_secs * 1000, now_ms);
+
+        let data = match options.codec.encode(&entry) {
+            Ok(data) => data,
+            Err(e) => {
+                // Still cached locally, sized by its JSON encoding
+                if let Ok(json) = serde_json::to_vec(&entry) {
+                    app_state.instance_cache.set_with_size(key, entry, local_ttl_secs, json.len());
+                }
+                return Err(format!("Failed to encode cached value for {}: {}", key, e));
+            },
+        };
+        let size = data.len();
+        let result = app_state.redis_client.set(key.to_string(), data, Some(options.retention_secs())).await;
+
+        app_state.instance_cache.set_with_size(key, entry, local_ttl_secs, size);
+        result
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
\ No newline at end of file
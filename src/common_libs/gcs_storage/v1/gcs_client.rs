@@ -2,8 +2,10 @@ use google_cloud_storage::client::{Client, ClientConfig};
 use google_cloud_storage::http::{
     buckets::{Bucket, get::GetBucketRequest},
     objects::{
+        delete::DeleteObjectRequest,
         download::Range,
         get::GetObjectRequest,
+        list::ListObjectsRequest,
         Object,
         upload::{UploadObjectRequest, UploadType},
     },
@@ -64,7 +66,6 @@ impl GCSClient {
         }
     }
 
-    #[allow(dead_code)]
     pub async fn upload_json_to_gcs<T>(
         &self,
         bucket_name: String,
@@ -96,4 +97,50 @@ impl GCSClient {
             Err(e) => Err(format!("Failed to upload object to {}/{}: {}", bucket_name, destination_path, e)),
         }
     }
+
+    /// List the names of all objects starting with `prefix`.
+    pub async fn list_objects(
+        &self,
+        bucket_name: &str,
+        prefix: &str,
+    ) -> Result<Vec<String>, String> {
+        let mut names = Vec::new();
+        let mut page_token = None;
+        loop {
+            let request = ListObjectsRequest {
+                bucket: bucket_name.to_string(),
+                prefix: Some(prefix.to_string()),
+                page_token,
+                ..Default::default()
+            };
+
+            let response = match self.client.list_objects(&request).await {
+                Ok(response) => response,
+                Err(e) => return Err(format!("Failed to list objects in {}/{}: {}", bucket_name, prefix, e)),
+            };
+            names.extend(response.items.unwrap_or_default().into_iter().map(|object| object.name));
+
+            match response.next_page_token {
+                Some(token) => page_token = Some(token),
+                None => return Ok(names),
+            }
+        }
+    }
+
+    pub async fn delete_object(
+        &self,
+        bucket_name: &str,
+        path: &str,
+    ) -> Result<(), String> {
+        let request = DeleteObjectRequest {
+            bucket: bucket_name.to_string(),
+            object: path.to_string(),
+            ..Default::default()
+        };
+
+        match self.client.delete_object(&request).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Failed to delete object {}/{}: {}", bucket_name, path, e)),
+        }
+    }
 }
\ No newline at end of file
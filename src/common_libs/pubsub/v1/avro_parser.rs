@@ -1,9 +1,60 @@
 use apache_avro::Schema;
-use apache_avro::to_avro_datum;
+use apache_avro::{from_avro_datum, to_avro_datum};
 use apache_avro::types::Value as AvroValue;
+use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::collections::HashMap;
 
+use super::avro_schema;
+use super::schema_compatibility::{self, SchemaCompatibility};
+
+// Length of `n` as a zigzag varint, as ints and longs are encoded
+fn varint_len(n: i64) -> usize {
+    let mut zigzag = ((n << 1) ^ (n >> 63)) as u64;
+    let mut len = 1;
+    while zigzag >= 0x80 {
+        zigzag >>= 7;
+        len += 1;
+    }
+    len
+}
+
+// Count and end marker of an array or map of `len` items, written as one block
+fn block_len(len: usize) -> usize {
+    match len {
+        0 => 1,
+        len => varint_len(len as i64) + 1,
+    }
+}
+
+fn encoded_len(value: &AvroValue) -> usize {
+    match value {
+        // Nullable fields are unions, null is only their branch index
+        AvroValue::Null => 0,
+        AvroValue::Boolean(_) => 1,
+        AvroValue::Int(n) | AvroValue::Date(n) | AvroValue::TimeMillis(n) => varint_len(*n as i64),
+        AvroValue::Long(n)
+        | AvroValue::TimeMicros(n)
+        | AvroValue::TimestampMillis(n)
+        | AvroValue::TimestampMicros(n)
+        | AvroValue::TimestampNanos(n) => varint_len(*n),
+        AvroValue::Float(_) => 4,
+        AvroValue::Double(_) => 8,
+        AvroValue::String(s) => varint_len(s.len() as i64) + s.len(),
+        AvroValue::Bytes(bytes) => varint_len(bytes.len() as i64) + bytes.len(),
+        AvroValue::Fixed(len, _) => *len,
+        AvroValue::Enum(index, _) => varint_len(*index as i64),
+        AvroValue::Union(index, value) => varint_len(*index as i64) + encoded_len(value),
+        // One block with its count, then the end marker
+        AvroValue::Array(items) => block_len(items.len()) + items.iter().map(encoded_len).sum::<usize>(),
+        AvroValue::Map(items) => block_len(items.len()) + items.iter()
+            .map(|(key, value)| varint_len(key.len() as i64) + key.len() + encoded_len(value))
+            .sum::<usize>(),
+        AvroValue::Record(fields) => fields.iter().map(|(_, value)| encoded_len(value)).sum(),
+        _ => 16,
+    }
+}
+
 pub struct AvroParser;
 
 impl AvroParser {
@@ -42,6 +93,10 @@ impl AvroParser {
                         continue;
                     }
                 }
+                // Left out, resolving writes the field's default
+                if field.default.is_some() {
+                    continue;
+                }
                 // If it’s not nullable, we have an error
                 return Err(format!("Field `{}` missing in struct and is not nullable!", name));
             }
@@ -67,10 +122,176 @@ impl AvroParser {
         //  Validate the Avro value with the schema
         let updated_avro_value = self.validate(avro_value, schema)?;
 
+        // Resolving applies the schema's type promotions and field defaults
+        let resolved_avro_value = match updated_avro_value.resolve(schema) {
+            Ok(value) => value,
+            Err(e) => return Err(format!("Failed to resolve Avro value with the schema: {}", e)),
+        };
+
         // Encode to binary Avro
-        match to_avro_datum(schema, updated_avro_value) {
+        match to_avro_datum(schema, resolved_avro_value) {
             Ok(encoded_data) => Ok(encoded_data),
             Err(e) => Err(format!("Failed to encode to Avro: {}", e)),
         }
     }
+
+    /// Check that stats of type `T` can be encoded with the schema.
+    ///
+    /// The schema of `T` is derived from the struct and resolved against it,
+    /// warnings report fields which are dropped or written as defaults.
+    pub fn check_struct<T>(
+        &self,
+        schema: &Schema
+    ) -> Result<SchemaCompatibility, String>
+    where
+        T: DeserializeOwned
+    {
+        let struct_schema = avro_schema::schema_of::<T>()?;
+        schema_compatibility::check_compatibility(&struct_schema, schema)
+    }
+
+    /// Size of `message` encoded as binary Avro, without needing the schema.
+    ///
+    /// Exact for types whose fields match the schema, including the union index
+    /// of nullable fields. Schema defaults and promotions are not accounted for.
+    pub fn estimate_encoded_len<T>(
+        &self,
+        message: &T,
+    ) -> Result<usize, String>
+    where
+        T: Serialize + ?Sized
+    {
+        match apache_avro::to_value(message) {
+            Ok(avro_value) => Ok(encoded_len(&avro_value)),
+            Err(e) => Err(format!("Failed to serialize to Avro: {}", e)),
+        }
+    }
+
+    /// Encode a JSON object with the schema, converting values where the schema allows.
+    pub fn encode_json(
+        &self,
+        data: &[u8],
+        schema: &Schema
+    ) -> Result<Vec<u8>, String> {
+        let json = match serde_json::from_slice::<serde_json::Value>(data) {
+            Ok(json) => json,
+            Err(e) => return Err(format!("Failed to parse JSON: {}", e)),
+        };
+        let avro_value = match apache_avro::to_value(&json) {
+            Ok(value) => value,
+            Err(e) => return Err(format!("Failed to convert JSON to Avro: {}", e)),
+        };
+
+        // Resolving turns the JSON object into a record and numbers into the schema's types
+        let resolved_avro_value = match avro_value.resolve(schema) {
+            Ok(value) => value,
+            Err(e) => return Err(format!("Failed to resolve Avro value with the schema: {}", e)),
+        };
+
+        match to_avro_datum(schema, resolved_avro_value) {
+            Ok(encoded_data) => Ok(encoded_data),
+            Err(e) => Err(format!("Failed to encode to Avro: {}", e)),
+        }
+    }
+
+    pub fn decode<T>(
+        &self,
+        data: &[u8],
+        schema: &Schema
+    ) -> Result<T, String>
+    where
+        T: DeserializeOwned
+    {
+        // Decode binary Avro
+        let avro_value = match from_avro_datum(schema, &mut &data[..], None) {
+            Ok(value) => value,
+            Err(e) => return Err(format!("Failed to decode Avro: {}", e)),
+        };
+
+        match apache_avro::from_value::<T>(&avro_value) {
+            Ok(message) => Ok(message),
+            Err(e) => Err(format!("Failed to deserialize Avro value: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common_libs::pubsub::v1::models::test_stats::TestStats;
+
+    fn test_stats_schema() -> Schema {
+        avro_schema::schema_of::<TestStats>().unwrap()
+    }
+
+    fn assert_estimate_matches(stats: &TestStats) {
+        let encoded = AvroParser.parse_and_encode(stats, &test_stats_schema()).unwrap();
+        assert_eq!(AvroParser.estimate_encoded_len(stats), Ok(encoded.len()), "{:?}", stats);
+    }
+
+    #[test]
+    fn varint_len_matches_encoding() {
+        for n in [0, 1, -1, 63, -64, 64, -65, 8191, 8192, i32::MAX as i64, i32::MIN as i64, i64::MAX, i64::MIN] {
+            let encoded = to_avro_datum(&Schema::Long, AvroValue::Long(n)).unwrap();
+            assert_eq!(varint_len(n), encoded.len(), "{}", n);
+        }
+    }
+
+    #[test]
+    fn encoded_len_matches_encoding() {
+        let cases = [
+            (Schema::parse_str(r#"{"type": "array", "items": "long"}"#).unwrap(), AvroValue::Array(Vec::new())),
+            (
+                Schema::parse_str(r#"{"type": "array", "items": "long"}"#).unwrap(),
+                AvroValue::Array((0..100).map(AvroValue::Long).collect()),
+            ),
+            (
+                Schema::parse_str(r#"{"type": "map", "values": "string"}"#).unwrap(),
+                AvroValue::Map(HashMap::from([("key".to_string(), AvroValue::String("value".to_string()))])),
+            ),
+            (Schema::parse_str(r#"["null", "string"]"#).unwrap(), AvroValue::Union(0, Box::new(AvroValue::Null))),
+            (
+                Schema::parse_str(r#"["null", "string"]"#).unwrap(),
+                AvroValue::Union(1, Box::new(AvroValue::String("x".repeat(200)))),
+            ),
+        ];
+        for (schema, value) in cases {
+            let encoded = to_avro_datum(&schema, value.clone()).unwrap();
+            assert_eq!(encoded_len(&value), encoded.len(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn estimate_matches_empty_stats() {
+        assert_estimate_matches(&TestStats {
+            event_type: "install".to_string(),
+            created_at: "2025-01-01 00:00:00".to_string(),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn estimate_matches_full_stats() {
+        let text = |len: usize| Some("x".repeat(len));
+        assert_estimate_matches(&TestStats {
+            event_type: "install".to_string(),
+            created_at: "2025-01-01 00:00:00".to_string(),
+            app_pkg: text(30),
+            guid: text(36),
+            country: text(2),
+            player_version: text(12),
+            oem: text(0),
+            machine_id: text(64),
+            version_machine_id: text(64),
+            instance: text(10),
+            image_name: text(20),
+            arg1: text(63),
+            arg2: text(64),
+            arg3: text(8191),
+            arg4: text(8192),
+            source: text(5),
+            count: Some(i32::MIN),
+            ad_refresh_rate: Some(-1),
+        });
+    }
 }
\ No newline at end of file
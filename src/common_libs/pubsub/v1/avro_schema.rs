@@ -0,0 +1,423 @@
+use apache_avro::Schema;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess, Visitor};
+use serde_json::{json, Value as JsonValue};
+use std::collections::HashSet;
+use std::fmt;
+
+// Deeper nesting is taken for a recursive type, which cannot be traced
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug)]
+struct TraceError(String);
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TraceError { }
+
+impl de::Error for TraceError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        TraceError(msg.to_string())
+    }
+}
+
+// Named types already defined, later occurrences only reference them
+struct TraceState {
+    named: HashSet<&'static str>,
+}
+
+/// Deserializer which records the Avro schema of what is asked of it.
+///
+/// Every value is deserialized once from a placeholder: options as `Some`,
+/// sequences and maps with one item, enums as their first variant.
+struct SchemaTracer<'a> {
+    state: &'a mut TraceState,
+    schema: &'a mut Option<JsonValue>,
+    depth: usize,
+}
+
+impl SchemaTracer<'_> {
+    fn nested<'b>(&'b mut self, schema: &'b mut Option<JsonValue>) -> Result<SchemaTracer<'b>, TraceError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(TraceError("Recursive types are not supported".to_string()));
+        }
+        Ok(SchemaTracer { state: self.state, schema, depth: self.depth + 1 })
+    }
+
+    fn unsupported<T>(what: &str) -> Result<T, TraceError> {
+        Err(TraceError(format!("{} cannot be mapped to an Avro schema", what)))
+    }
+}
+
+macro_rules! trace_primitive {
+    ($method:ident, $visit:ident, $avro_type:expr, $placeholder:expr) => {
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+            *self.schema = Some(json!($avro_type));
+            visitor.$visit($placeholder)
+        }
+    };
+}
+
+impl<'de> de::Deserializer<'de> for SchemaTracer<'_> {
+    type Error = TraceError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Self::unsupported("A self-describing type (untagged or flattened)")
+    }
+
+    trace_primitive!(deserialize_bool, visit_bool, "boolean", false);
+    trace_primitive!(deserialize_i8, visit_i8, "int", 0);
+    trace_primitive!(deserialize_i16, visit_i16, "int", 0);
+    trace_primitive!(deserialize_i32, visit_i32, "int", 0);
+    trace_primitive!(deserialize_i64, visit_i64, "long", 0);
+    trace_primitive!(deserialize_u8, visit_u8, "int", 0);
+    trace_primitive!(deserialize_u16, visit_u16, "int", 0);
+    trace_primitive!(deserialize_u32, visit_u32, "long", 0);
+    trace_primitive!(deserialize_u64, visit_u64, "long", 0);
+    trace_primitive!(deserialize_f32, visit_f32, "float", 0.0);
+    trace_primitive!(deserialize_f64, visit_f64, "double", 0.0);
+    trace_primitive!(deserialize_char, visit_char, "string", ' ');
+    trace_primitive!(deserialize_str, visit_str, "string", "");
+    trace_primitive!(deserialize_string, visit_str, "string", "");
+    trace_primitive!(deserialize_identifier, visit_str, "string", "");
+    trace_primitive!(deserialize_bytes, visit_bytes, "bytes", &[]);
+    trace_primitive!(deserialize_byte_buf, visit_bytes, "bytes", &[]);
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        *self.schema = Some(json!("null"));
+        visitor.visit_unit()
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, Self::Error> {
+        let mut inner = None;
+        let value = visitor.visit_some(self.nested(&mut inner)?)?;
+        *self.schema = match inner {
+            Some(JsonValue::Array(_)) => return Self::unsupported("A nested option"),
+            Some(inner) => Some(json!(["null", inner])),
+            None => return Self::unsupported("An option of nothing"),
+        };
+        Ok(value)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, Self::Error> {
+        let mut items = None;
+        let value = visitor.visit_seq(SingleItem { tracer: Some(self.nested(&mut items)?) })?;
+        *self.schema = match items {
+            Some(items) => Some(json!({ "type": "array", "items": items })),
+            None => return Self::unsupported("An array of nothing"),
+        };
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, _visitor: V) -> Result<V::Value, Self::Error> {
+        Self::unsupported("A tuple")
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, name: &'static str, _len: usize, _visitor: V) -> Result<V::Value, Self::Error> {
+        Self::unsupported(&format!("Tuple struct {}", name))
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, Self::Error> {
+        let mut values = None;
+        let value = visitor.visit_map(SingleEntry { tracer: Some(self.nested(&mut values)?), key_done: false })?;
+        *self.schema = match values {
+            Some(values) => Some(json!({ "type": "map", "values": values })),
+            None => return Self::unsupported("A map of nothing"),
+        };
+        Ok(value)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        if self.depth >= MAX_DEPTH {
+            return Self::unsupported(&format!("Recursive struct {}", name));
+        }
+        let mut field_schemas = Vec::with_capacity(fields.len());
+        let value = visitor.visit_map(StructFields {
+            state: self.state,
+            depth: self.depth + 1,
+            fields,
+            schemas: &mut field_schemas,
+        })?;
+
+        *self.schema = match self.state.named.insert(name) {
+            true => {
+                let fields = fields.iter().zip(field_schemas)
+                    .map(|(field, schema)| match &schema {
+                        // Null first, so that the field can default to it
+                        JsonValue::Array(_) => json!({ "name": field, "type": schema, "default": null }),
+                        _ => json!({ "name": field, "type": schema }),
+                    })
+                    .collect::<Vec<_>>();
+                Some(json!({ "type": "record", "name": name, "fields": fields }))
+            },
+            false => Some(json!(name)),
+        };
+        Ok(value)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        if variants.is_empty() {
+            return Self::unsupported(&format!("Enum {} without variants", name));
+        }
+        let value = visitor.visit_enum(FirstVariant { name, variant: variants[0] })?;
+
+        *self.schema = match self.state.named.insert(name) {
+            true => Some(json!({ "type": "enum", "name": name, "symbols": variants })),
+            false => Some(json!(name)),
+        };
+        Ok(value)
+    }
+}
+
+struct SingleItem<'a> {
+    tracer: Option<SchemaTracer<'a>>,
+}
+
+impl<'de> SeqAccess<'de> for SingleItem<'_> {
+    type Error = TraceError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error> {
+        match self.tracer.take() {
+            Some(tracer) => seed.deserialize(tracer).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+struct SingleEntry<'a> {
+    tracer: Option<SchemaTracer<'a>>,
+    key_done: bool,
+}
+
+impl<'de> MapAccess<'de> for SingleEntry<'_> {
+    type Error = TraceError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error> {
+        match self.tracer.is_some() && !self.key_done {
+            true => {
+                // Avro map keys are strings
+                self.key_done = true;
+                seed.deserialize("".into_deserializer()).map(Some)
+            },
+            false => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Self::Error> {
+        match self.tracer.take() {
+            Some(tracer) => seed.deserialize(tracer),
+            None => Err(TraceError("Map value requested twice".to_string())),
+        }
+    }
+}
+
+struct StructFields<'a, 'b> {
+    state: &'a mut TraceState,
+    depth: usize,
+    fields: &'static [&'static str],
+    schemas: &'b mut Vec<JsonValue>,
+}
+
+impl<'de> MapAccess<'de> for StructFields<'_, '_> {
+    type Error = TraceError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error> {
+        match self.fields.get(self.schemas.len()) {
+            Some(field) => seed.deserialize(field.into_deserializer()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Self::Error> {
+        let mut schema = None;
+        let value = seed.deserialize(SchemaTracer {
+            state: self.state,
+            schema: &mut schema,
+            depth: self.depth,
+        })?;
+        match schema {
+            Some(schema) => self.schemas.push(schema),
+            None => return Err(TraceError(format!("No schema traced for field {}", self.fields[self.schemas.len()]))),
+        }
+        Ok(value)
+    }
+}
+
+struct FirstVariant {
+    name: &'static str,
+    variant: &'static str,
+}
+
+impl<'de> EnumAccess<'de> for FirstVariant {
+    type Error = TraceError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error> {
+        let value = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((value, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for FirstVariant {
+    type Error = TraceError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, _seed: T) -> Result<T::Value, Self::Error> {
+        SchemaTracer::unsupported(&format!("Enum {} with data", self.name))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, _visitor: V) -> Result<V::Value, Self::Error> {
+        SchemaTracer::unsupported(&format!("Enum {} with data", self.name))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], _visitor: V) -> Result<V::Value, Self::Error> {
+        SchemaTracer::unsupported(&format!("Enum {} with data", self.name))
+    }
+}
+
+/// Avro schema of `T` as JSON, derived from its `Deserialize` implementation.
+///
+/// Options become unions with null first, defaulting to null. Only enums of
+/// unit variants are supported. Strings are traced with an empty value, so
+/// types parsed from strings (e.g. timestamps) cannot be traced.
+pub fn schema_json_of<T: DeserializeOwned>() -> Result<JsonValue, String> {
+    let mut state = TraceState { named: HashSet::new() };
+    let mut schema = None;
+    let tracer = SchemaTracer { state: &mut state, schema: &mut schema, depth: 0 };
+
+    if let Err(e) = T::deserialize(tracer) {
+        return Err(format!("Failed to derive Avro schema of {}: {}", std::any::type_name::<T>(), e));
+    }
+    match schema {
+        Some(schema) => Ok(schema),
+        None => Err(format!("Failed to derive Avro schema of {}: nothing traced", std::any::type_name::<T>())),
+    }
+}
+
+/// Avro schema of `T`, derived from its `Deserialize` implementation.
+pub fn schema_of<T: DeserializeOwned>() -> Result<Schema, String> {
+    let schema_json = schema_json_of::<T>()?;
+    match Schema::parse(&schema_json) {
+        Ok(schema) => Ok(schema),
+        Err(e) => Err(format!("Failed to parse derived Avro schema of {}: {}", std::any::type_name::<T>(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    use crate::common_libs::pubsub::v1::models::test_stats::TestStats;
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum Level {
+        Low,
+        High,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Point {
+        x: f64,
+        y: f32,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Shape {
+        id: u32,
+        total: i64,
+        visible: bool,
+        level: Level,
+        origin: Point,
+        corners: Vec<Point>,
+        tags: HashMap<String, String>,
+        data: Option<Vec<u8>>,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Node {
+        children: Vec<Node>,
+    }
+
+    fn field<'a>(schema: &'a JsonValue, name: &str) -> &'a JsonValue {
+        schema["fields"].as_array().unwrap().iter()
+            .find(|field| field["name"] == name)
+            .unwrap_or_else(|| panic!("missing field {}", name))
+    }
+
+    #[test]
+    fn test_stats_schema() {
+        let schema_json = schema_json_of::<TestStats>().unwrap();
+        assert_eq!(schema_json["type"], "record");
+        assert_eq!(schema_json["name"], "TestStats");
+        assert_eq!(schema_json["fields"].as_array().unwrap().len(), 18);
+        assert_eq!(field(&schema_json, "event_type")["type"], json!("string"));
+        assert_eq!(field(&schema_json, "app_pkg")["type"], json!(["null", "string"]));
+        assert_eq!(field(&schema_json, "app_pkg")["default"], JsonValue::Null);
+        assert_eq!(field(&schema_json, "count")["type"], json!(["null", "int"]));
+
+        match schema_of::<TestStats>().unwrap() {
+            Schema::Record(record) => assert_eq!(record.fields[0].name, "event_type"),
+            other => panic!("expected a record, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nested_types_schema() {
+        let schema_json = schema_json_of::<Shape>().unwrap();
+        assert_eq!(field(&schema_json, "id")["type"], json!("long"));
+        assert_eq!(field(&schema_json, "total")["type"], json!("long"));
+        assert_eq!(field(&schema_json, "visible")["type"], json!("boolean"));
+        assert_eq!(field(&schema_json, "level")["type"]["type"], "enum");
+        assert_eq!(field(&schema_json, "level")["type"]["symbols"], json!(["Low", "High"]));
+
+        let origin = &field(&schema_json, "origin")["type"];
+        assert_eq!(origin["name"], "Point");
+        assert_eq!(field(origin, "x")["type"], json!("double"));
+        assert_eq!(field(origin, "y")["type"], json!("float"));
+        // Later occurrences of a named type reference it
+        assert_eq!(field(&schema_json, "corners")["type"], json!({"type": "array", "items": "Point"}));
+        assert_eq!(field(&schema_json, "tags")["type"], json!({"type": "map", "values": "string"}));
+
+        assert!(schema_of::<Shape>().is_ok());
+    }
+
+    #[test]
+    fn recursive_type_is_rejected() {
+        let error = schema_json_of::<Node>().unwrap_err();
+        assert!(error.contains("Recursive struct Node"), "{}", error);
+    }
+}
\ No newline at end of file
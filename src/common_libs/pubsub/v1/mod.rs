@@ -1,6 +1,14 @@
 pub mod avro_parser;
+pub mod avro_schema;
 pub mod bigquery_constants;
 pub mod models;
 pub mod pubsub_client;
 pub mod pubsub_constants;
-pub mod pubsub_publisher;
\ No newline at end of file
+pub mod pubsub_publisher;
+pub mod pubsub_push;
+pub mod pubsub_push_auth;
+pub mod pubsub_spill;
+pub mod pubsub_subscriber;
+pub mod schema_compatibility;
+pub mod stat_queue;
+pub mod stat_registry;
\ No newline at end of file
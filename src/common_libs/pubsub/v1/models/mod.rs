@@ -1,7 +1,12 @@
 pub mod test_stats;
 
-use erased_serde::{serialize, serialize_trait_object};
-use serde_json::Serializer;
+use erased_serde::serialize_trait_object;
+use std::collections::HashMap;
+
+use super::bigquery_constants::{bigquery_dataset, bigquery_table};
+use super::pubsub_constants::{pubsub_schema, pubsub_topic};
+use super::stat_registry::RegisteredStat;
+use test_stats::TestStats;
 
 #[macro_export]
 macro_rules! make_stats {
@@ -23,16 +28,71 @@ macro_rules! make_stats {
 }
 
 pub trait StatRecord: erased_serde::Serialize + Send + Sync {
-    /// Get the data size in bytes
-    fn data_len(&self) -> usize {
-        let mut buffer = Vec::new();
-        let mut serializer = Serializer::new(&mut buffer);
-
-        match serialize(self, &mut serializer) {
-            Ok(_) => buffer.len(),
-            Err(_) => 0,
-        }
+    /// Attributes of the published message, e.g. for subscription filters.
+    fn attributes(&self) -> HashMap<String, String> {
+        HashMap::new()
+    }
+
+    /// Messages sharing an ordering key are delivered in publish order to
+    /// subscriptions with message ordering enabled.
+    ///
+    /// Within a batch, the messages following a failed one of the same key are
+    /// held back and spilled with it. Spilled batches are replayed later, so the
+    /// order across batches is best-effort.
+    fn ordering_key(&self) -> Option<String> {
+        None
     }
 }
 
-serialize_trait_object!(StatRecord);
\ No newline at end of file
+serialize_trait_object!(StatRecord);
+
+/// Where stats of a type are published and stored.
+#[derive(Debug)]
+pub struct StatBinding {
+    pub dataset: &'static str,
+    pub table: &'static str,
+    pub topic: &'static str,
+    // Avro schema of the topic, in the project of the topic
+    pub schema_id: &'static str,
+}
+
+mod sealed {
+    // Implemented by `bind_stats!` only, so that every bound stat type is registered
+    pub trait Registered {}
+}
+
+/// Stat type bound to its topic and schema at compile time.
+///
+/// Sealed, types are bound in `bind_stats!` below, which registers them for
+/// the checks on startup.
+pub trait BoundStat: StatRecord + Sized + 'static + sealed::Registered {
+    const BINDING: &'static StatBinding;
+}
+
+macro_rules! bind_stats {
+    ($($stat:ident => $binding:expr),* $(,)?) => {
+        $(
+            impl sealed::Registered for $stat {}
+
+            impl BoundStat for $stat {
+                const BINDING: &'static StatBinding = &$binding;
+            }
+        )*
+
+        /// Every stat type published by the application.
+        pub fn registered_stats() -> Vec<RegisteredStat> {
+            vec![
+                $(RegisteredStat::of::<$stat>(),)*
+            ]
+        }
+    };
+}
+
+bind_stats! {
+    TestStats => StatBinding {
+        dataset: bigquery_dataset::STATS,
+        table: bigquery_table::TEST_STATS,
+        topic: pubsub_topic::TEST_STATS,
+        schema_id: pubsub_schema::TEST_STATS,
+    },
+}
\ No newline at end of file
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 use super::StatRecord;
 
@@ -24,4 +25,8 @@ pub struct TestStats {
     pub ad_refresh_rate: Option<i32>,
 }
 
-impl StatRecord for TestStats { }
\ No newline at end of file
+impl StatRecord for TestStats {
+    fn attributes(&self) -> HashMap<String, String> {
+        HashMap::from([("event_type".to_string(), self.event_type.clone())])
+    }
+}
\ No newline at end of file
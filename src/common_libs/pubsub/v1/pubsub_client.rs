@@ -1,24 +1,157 @@
 use apache_avro::Schema;
+use deadpool_redis::redis::Script;
 use google_cloud_gax::conn::Channel;
+use google_cloud_gax::grpc::{Code, Status};
 use google_cloud_googleapis::pubsub::v1::{
+    Encoding,
     GetSchemaRequest,
+    GetTopicRequest,
     PubsubMessage,
     SchemaView,
+    publisher_client::PublisherClient,
     schema_service_client::SchemaServiceClient,
 };
 use google_cloud_pubsub::apiv1::conn_pool::ConnectionManager;
 use google_cloud_pubsub::client::{Client, ClientConfig};
+use google_cloud_pubsub::publisher::{Publisher, PublisherConfig};
+use google_cloud_pubsub::subscription::Subscription;
+use once_cell::sync::Lazy;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
 use tonic::Request;
 
+use crate::common_libs::cache_service::v1::{
+    cache_key::CacheNamespace,
+    tiered_cache::{CacheOptions, RELEASE_LOCK_SCRIPT},
+};
+use crate::config::PubSubRetryConfig;
 use crate::state::APP_STATE;
 use super::avro_parser;
-use super::pubsub_constants::pubsub_topic;
+use super::pubsub_constants::{pubsub_attribute, pubsub_topic};
 use super::pubsub_publisher::StatWithMetadata;
+use super::pubsub_spill::{SpillEncoding, SpilledBatch, SpilledMessage};
+
+const SCHEMA_LOAD_LOCK_MS: u64 = 2000;
+const SCHEMA_CACHE_NAMESPACE: CacheNamespace = CacheNamespace::new("pubsub_schema", 1);
+// Error of the messages spilled once the shutdown deadline is reached, sent or not
+const SHUTDOWN_DEADLINE_ERROR: &str = "Shutdown deadline reached before the messages were confirmed";
+const REPLAY_LEASE_KEY: &str = "pubsub_spill:replay_lease";
+// Renewed before each batch, a replay which stops renewing frees the lease after this
+const REPLAY_LEASE_MS: u64 = 120_000;
+
+// KEYS[1] = lease key, ARGV[1] = token of the holder, ARGV[2] = lease in ms
+static RENEW_LEASE_SCRIPT: Lazy<Script> = Lazy::new(|| Script::new(r"
+    if redis.call('GET', KEYS[1]) == ARGV[1] then
+        return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+    end
+    return 0
+"));
+
+#[derive(Debug, Default, Serialize)]
+pub struct ReplayReport {
+    pub batches: usize,
+    pub failed_batches: usize,
+    pub replayed_messages: usize,
+    pub failed_messages: usize,
+}
+
+fn is_retryable(status: &Status) -> bool {
+    matches!(
+        status.code(),
+        Code::Unavailable
+            | Code::DeadlineExceeded
+            | Code::ResourceExhausted
+            | Code::Aborted
+            | Code::Internal
+            | Code::Unknown
+            | Code::Cancelled
+    )
+}
+
+// Exponential backoff with jitter before the attempt following `attempt`
+fn retry_backoff(retry: &PubSubRetryConfig, attempt: u32) -> Duration {
+    let backoff_ms = retry.initial_backoff_ms
+        .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+        .min(retry.max_backoff_ms);
+    Duration::from_millis(backoff_ms / 2 + rand::random::<u64>() % (backoff_ms / 2 + 1))
+}
+
+// Outcomes with the messages following a failed one of the same ordering key held back,
+// unless they were published
+fn hold_back_failed_keys(
+    messages: &[PubsubMessage],
+    outcomes: Vec<Result<String, Status>>,
+) -> Vec<Option<Result<String, Status>>> {
+    let mut failed_keys = HashSet::new();
+    messages.iter().zip(outcomes)
+        .map(|(message, outcome)| {
+            let ordering_key = message.ordering_key.as_str();
+            match outcome {
+                Ok(message_id) => Some(Ok(message_id)),
+                Err(_) if failed_keys.contains(ordering_key) => None,
+                Err(status) => {
+                    if !ordering_key.is_empty() {
+                        failed_keys.insert(ordering_key);
+                    }
+                    Some(Err(status))
+                },
+            }
+        })
+        .collect()
+}
+
+// Split messages into those to retry and those given up, with the last publish error.
+// Held back messages share the fate of the failed message of their ordering key
+fn partition_outcomes(
+    messages: Vec<PubsubMessage>,
+    outcomes: Vec<Option<Result<String, Status>>>,
+    can_retry: bool,
+) -> (Vec<PubsubMessage>, Vec<PubsubMessage>, Option<String>) {
+    // Ordering keys whose first failed message is retried
+    let mut retried_keys = HashSet::new();
+    let mut retryable = Vec::new();
+    let mut failed = Vec::new();
+    let mut last_error = None;
+    for (message, outcome) in messages.into_iter().zip(outcomes) {
+        let is_retried = match outcome {
+            Some(Ok(_)) => continue,
+            Some(Err(status)) => {
+                last_error = Some(format!("Failed to publish message: {}", status));
+                let is_retried = can_retry && is_retryable(&status);
+                if is_retried && !message.ordering_key.is_empty() {
+                    retried_keys.insert(message.ordering_key.clone());
+                }
+                is_retried
+            },
+            // Held back behind a failed message of its ordering key
+            None => retried_keys.contains(&message.ordering_key),
+        };
+        if is_retried {
+            retryable.push(message);
+        }
+        else {
+            failed.push(message);
+        }
+    }
+    (retryable, failed, last_error)
+}
+
+fn parse_schema(schema_definition: &str) -> Result<Schema, String> {
+    match Schema::parse_str(schema_definition) {
+        Ok(schema) => Ok(schema),
+        Err(e) => Err(format!("Failed to parse schema: {}", e)),
+    }
+}
 
 pub struct PubSubClient {
     client: Client,
+    publishers: std::sync::Mutex<HashMap<String, Publisher>>,
     schema_client: Mutex<SchemaServiceClient<Channel>>,
+    topic_client: Mutex<PublisherClient<Channel>>,
     avro_parser: avro_parser::AvroParser,
 }
 
@@ -35,64 +168,356 @@ impl PubSubClient {
         .await.unwrap();
 
         let schema_client = Mutex::new(SchemaServiceClient::new(cm.conn()));
+        let topic_client = Mutex::new(PublisherClient::new(cm.conn()));
 
         let client = Client::new(config).await.unwrap();
         let avro_parser = avro_parser::AvroParser;
 
-        Self { client, schema_client, avro_parser }
+        let publishers = std::sync::Mutex::new(HashMap::new());
+
+        Self { client, publishers, schema_client, topic_client, avro_parser }
+    }
+
+    pub fn subscription(&self, subscription_id: &str) -> Subscription {
+        self.client.subscription(subscription_id)
+    }
+
+    /// Decode a received message, as Avro of the topic schema if the topic has one and as JSON otherwise.
+    pub async fn decode_message<T>(
+        &self,
+        data: &[u8],
+        attributes: &HashMap<String, String>,
+    ) -> Result<T, String>
+    where
+        T: DeserializeOwned,
+    {
+        let schema_id = attributes.get(pubsub_attribute::SCHEMA_NAME)
+            .and_then(|schema_name| schema_name.rsplit('/').next());
+        let is_json = attributes.get(pubsub_attribute::SCHEMA_ENCODING)
+            .is_some_and(|encoding| encoding == "JSON");
+
+        match schema_id {
+            Some(schema_id) if !is_json => {
+                let schema = self.get_schema(schema_id).await?;
+                self.avro_parser.decode(data, &schema)
+            },
+            _ => match serde_json::from_slice::<T>(data) {
+                Ok(message) => Ok(message),
+                Err(e) => Err(format!("Failed to parse message JSON: {}", e)),
+            },
+        }
     }
 
+    /// Publish a batch of stats, spilling what could not be published.
+    ///
+    /// Once `abandon` is cancelled, what is not published yet is spilled right away.
     pub async fn publish_batch(
         &self,
         topic: &str,
         batch: Vec<StatWithMetadata>,
+        abandon: &CancellationToken,
     ) {
         if batch.is_empty() {
             return;
         }
 
-        let publisher = self.client.topic(topic).new_publisher(None);
-        let mut avro_data: Vec<PubsubMessage> = Vec::new();
-        let schema = match self.get_schema(topic).await {
+        let binding = batch.first().unwrap().binding;
+        let schema = tokio::select! {
+            biased;
+            _ = abandon.cancelled() => Err(SHUTDOWN_DEADLINE_ERROR.to_string()),
+            schema = self.get_schema_with_retry(binding.schema_id) => schema,
+        };
+        let schema = match schema {
             Ok(schema) => schema,
             Err(e) => {
                 tracing::error!("Could not get schema - err: {:?}", &e);
+                // Kept as JSON, encoded with the schema on replay
+                let messages = batch.iter()
+                    .filter_map(|stat_with_metadata| match serde_json::to_vec(&stat_with_metadata.stat) {
+                        Ok(data) => Some(SpilledMessage::from_pubsub(&Self::stat_message(stat_with_metadata, data))),
+                        Err(e) => {
+                            tracing::error!("Could not serialize stat for spilling - err: {:?}", &e);
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                self.spill(SpilledBatch::new(topic, binding.schema_id, SpillEncoding::Json, &e, messages)).await;
+                if !abandon.is_cancelled() {
+                    let _ = self.publish_err_message(binding.dataset, binding.table, &e).await;
+                }
                 return;
             }
         };
 
+        let mut avro_data: Vec<PubsubMessage> = Vec::new();
         for stat_with_metadata in &batch {
             let stat = &stat_with_metadata.stat;
 
             match self.avro_parser.parse_and_encode(stat, &schema) {
-                Ok(data) => {
-                    let pubsub_message = PubsubMessage {
-                        data: data.into(),
-                        ..Default::default()
-                    };
-                    avro_data.push(pubsub_message)
-                },
+                Ok(data) => avro_data.push(Self::stat_message(stat_with_metadata, data)),
                 Err(e) => {
                     tracing::error!("Could not parse to avro - err: {:?}", &e);
                 }
             }
         }
 
-        let awaiter = publisher.publish_bulk(avro_data).await;
-        for result in awaiter {
-            match result.get().await {
-                Ok(_) => {}
+        if let Err((failed, e)) = self.publish_with_retry(topic, avro_data, abandon).await {
+            tracing::error!("Could not publish {} messages to {} - err: {}", failed.len(), topic, e);
+            let messages = failed.iter().map(SpilledMessage::from_pubsub).collect();
+            self.spill(SpilledBatch::new(topic, binding.schema_id, SpillEncoding::Avro, &e, messages)).await;
+            if !abandon.is_cancelled() {
+                let _ = self.publish_err_message(binding.dataset, binding.table, &e).await;
+            }
+        }
+    }
+
+    fn stat_message(stat_with_metadata: &StatWithMetadata, data: Vec<u8>) -> PubsubMessage {
+        PubsubMessage {
+            data,
+            attributes: stat_with_metadata.stat.attributes(),
+            ordering_key: stat_with_metadata.stat.ordering_key().unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    /// Publisher of `topic`, created once with the configured settings.
+    fn publisher(&self, topic: &str) -> Publisher {
+        let mut publishers = self.publishers.lock().unwrap();
+        if let Some(publisher) = publishers.get(topic) {
+            return publisher.clone();
+        }
+
+        let settings = &APP_STATE.get().unwrap().config.pubsub.publisher;
+        let publisher_config = PublisherConfig {
+            workers: settings.workers.max(1),
+            flush_interval: Duration::from_millis(settings.flush_interval_ms),
+            bundle_size: settings.bundle_size.max(1),
+            retry_setting: None,
+        };
+        let publisher = self.client.topic(topic).new_publisher(Some(publisher_config));
+        publishers.insert(topic.to_string(), publisher.clone());
+        publisher
+    }
+
+    /// Send everything the cached publishers still hold and stop them.
+    pub async fn shutdown_publishers(&self) {
+        let publishers = std::mem::take(&mut *self.publishers.lock().unwrap());
+        for (_, mut publisher) in publishers {
+            publisher.shutdown().await;
+        }
+    }
+
+    /// Publish `messages`, retrying retryable failures with exponential backoff.
+    ///
+    /// Once a message with an ordering key fails, the later messages of that key
+    /// are held back and retried or returned together with it, in their order.
+    ///
+    /// Returns the messages which could not be published and the last error.
+    /// Once `abandon` is cancelled, the messages not published yet are returned.
+    async fn publish_with_retry(
+        &self,
+        topic: &str,
+        messages: Vec<PubsubMessage>,
+        abandon: &CancellationToken,
+    ) -> Result<(), (Vec<PubsubMessage>, String)> {
+        let retry = &APP_STATE.get().unwrap().config.pubsub.retry;
+        let publisher = self.publisher(topic);
+
+        let mut pending = messages;
+        let mut failed = Vec::new();
+        let mut last_error = String::new();
+        let mut attempt = 1;
+        while !pending.is_empty() {
+            let outcomes = tokio::select! {
+                biased;
+                _ = abandon.cancelled() => None,
+                outcomes = Self::publish_ordered(&publisher, &pending) => Some(outcomes),
+            };
+            let outcomes = match outcomes {
+                Some(outcomes) => outcomes,
+                None => {
+                    failed.extend(pending);
+                    last_error = SHUTDOWN_DEADLINE_ERROR.to_string();
+                    break;
+                }
+            };
+
+            let (retryable, given_up, error) = partition_outcomes(pending, outcomes, attempt < retry.max_attempts);
+            failed.extend(given_up);
+            if let Some(error) = error {
+                last_error = error;
+            }
+
+            pending = retryable;
+            if !pending.is_empty() {
+                tracing::warn!("Retrying {} messages to {} after attempt {} - err: {}", pending.len(), topic, attempt, last_error);
+                tokio::select! {
+                    _ = tokio::time::sleep(retry_backoff(retry, attempt)) => {},
+                    _ = abandon.cancelled() => {},
+                }
+                attempt += 1;
+            }
+        }
+        match failed.is_empty() {
+            true => Ok(()),
+            false => Err((failed, last_error)),
+        }
+    }
+
+    /// Publish `messages` once in a single bulk, returning the outcome of each in order.
+    ///
+    /// The publisher sends the messages of an ordering key in order on one stream.
+    /// After a failure, the later unpublished messages of the key have no outcome.
+    async fn publish_ordered(
+        publisher: &Publisher,
+        messages: &[PubsubMessage],
+    ) -> Vec<Option<Result<String, Status>>> {
+        let awaiters = publisher.publish_bulk(messages.to_vec()).await;
+        let mut outcomes = Vec::with_capacity(awaiters.len());
+        for awaiter in awaiters {
+            outcomes.push(awaiter.get().await);
+        }
+        hold_back_failed_keys(messages, outcomes)
+    }
+
+    async fn get_schema_with_retry(&self, schema_id: &str) -> Result<Schema, String> {
+        let retry = &APP_STATE.get().unwrap().config.pubsub.retry;
+        let mut attempt = 1;
+        loop {
+            match self.get_schema(schema_id).await {
+                Ok(schema) => return Ok(schema),
+                Err(e) if attempt < retry.max_attempts => {
+                    tracing::warn!("Retrying schema fetch for {} after attempt {} - err: {}", schema_id, attempt, e);
+                    tokio::time::sleep(retry_backoff(retry, attempt)).await;
+                    attempt += 1;
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn spill(&self, batch: SpilledBatch) {
+        if batch.messages.is_empty() {
+            return;
+        }
+
+        let name = batch.new_name();
+        match batch.save(&name).await {
+            Ok(_) => tracing::warn!("Spilled {} messages for {} to {}", batch.messages.len(), batch.topic, name),
+            Err(e) => tracing::error!("Could not spill {} messages for {}, they are lost - err: {}", batch.messages.len(), batch.topic, e),
+        }
+    }
+
+    /// Republish all spilled batches, removing each one once fully published.
+    ///
+    /// Replays hold a lease in Redis, so that replays on several instances do
+    /// not publish the same batches. Returns `None` if another replay holds it.
+    pub async fn replay_spilled(&self) -> Result<Option<ReplayReport>, String> {
+        let app_state = APP_STATE.get().unwrap();
+        let lease_key = REPLAY_LEASE_KEY.to_string();
+        let lease_token = format!("{:016x}", rand::random::<u64>());
+
+        match app_state.redis_client.set_nx(&lease_key, lease_token.as_str(), REPLAY_LEASE_MS).await {
+            Ok(true) => (),
+            Ok(false) => return Ok(None),
+            Err(e) => return Err(format!("Failed to acquire replay lease: {}", e)),
+        }
+
+        let result = self.replay_spilled_batches(&lease_key, &lease_token).await;
+
+        if let Err(e) = app_state.redis_client.eval_script::<&str, i64>(
+            &RELEASE_LOCK_SCRIPT,
+            std::slice::from_ref(&lease_key),
+            &[lease_token.as_str()],
+        ).await {
+            tracing::error!("Failed to release replay lease - err: {}", e);
+        }
+        result.map(Some)
+    }
+
+    async fn replay_spilled_batches(&self, lease_key: &str, lease_token: &str) -> Result<ReplayReport, String> {
+        let app_state = APP_STATE.get().unwrap();
+        let lease_keys = [lease_key.to_string()];
+        let lease_ms = REPLAY_LEASE_MS.to_string();
+
+        let mut report = ReplayReport::default();
+        for name in SpilledBatch::list().await? {
+            // Stop once the lease is lost, another replay may have taken over
+            match app_state.redis_client.eval_script::<&str, i64>(&RENEW_LEASE_SCRIPT, &lease_keys, &[lease_token, lease_ms.as_str()]).await {
+                Ok(1) => (),
+                Ok(_) => return Err(format!("Lost the replay lease after {} batches", report.batches)),
+                Err(e) => return Err(format!("Failed to renew replay lease: {}", e)),
+            }
+            report.batches += 1;
+
+            let mut batch = match SpilledBatch::load(&name).await {
+                Ok(batch) => batch,
                 Err(e) => {
-                    tracing::error!("Could not publish message - err: {:?}", e);
-                    let first_item = batch.first().unwrap();
-                    let _ = self.publish_err_message(
-                        &first_item.dataset_id,
-                        &first_item.table_id,
-                        &e.to_string(),
-                    ).await;
+                    tracing::error!("Could not load spilled batch {} - err: {}", name, e);
+                    report.failed_batches += 1;
+                    continue;
+                }
+            };
+
+            let messages = match self.spilled_to_pubsub(&batch).await {
+                Ok(messages) => messages,
+                Err(e) => {
+                    tracing::error!("Could not prepare spilled batch {} - err: {}", name, e);
+                    report.failed_batches += 1;
+                    continue;
+                }
+            };
+            let message_count = messages.len();
+
+            match self.publish_with_retry(&batch.topic, messages, &CancellationToken::new()).await {
+                Ok(_) => {
+                    report.replayed_messages += message_count;
+                    if let Err(e) = SpilledBatch::remove(&name).await {
+                        tracing::error!("Could not remove replayed batch {} - err: {}", name, e);
+                    }
+                },
+                Err((failed, e)) => {
+                    tracing::error!("Could not replay {} messages of {} - err: {}", failed.len(), name, e);
+                    report.replayed_messages += message_count - failed.len();
+                    report.failed_messages += failed.len();
+                    report.failed_batches += 1;
+
+                    // Keep only what is left, already encoded
+                    batch.encoding = SpillEncoding::Avro;
+                    batch.error = e;
+                    batch.messages = failed.iter().map(SpilledMessage::from_pubsub).collect();
+                    if let Err(e) = batch.save(&name).await {
+                        tracing::error!("Could not update spilled batch {} - err: {}", name, e);
+                    }
                 }
             }
         }
+
+        tracing::info!("Replayed spilled batches - report: {:?}", report);
+        Ok(report)
+    }
+
+    async fn spilled_to_pubsub(&self, batch: &SpilledBatch) -> Result<Vec<PubsubMessage>, String> {
+        let schema = match batch.encoding {
+            SpillEncoding::Avro => None,
+            SpillEncoding::Json => Some(self.get_schema(batch.schema_id()).await?),
+        };
+
+        let mut messages = Vec::with_capacity(batch.messages.len());
+        for spilled_message in &batch.messages {
+            let data = spilled_message.decode_data()?;
+            let data = match &schema {
+                Some(schema) => self.avro_parser.encode_json(&data, schema)?,
+                None => data,
+            };
+            messages.push(PubsubMessage {
+                data,
+                attributes: spilled_message.attributes.clone(),
+                ordering_key: spilled_message.ordering_key.clone(),
+                ..Default::default()
+            });
+        }
+        Ok(messages)
     }
 
     async fn publish_err_message(
@@ -108,7 +533,7 @@ impl PubSubClient {
         }).to_string();
 
         let topic_id = pubsub_topic::GENERAL_ERROR_TOPIC;
-        let publisher = self.client.topic(topic_id).new_publisher(None);
+        let publisher = self.publisher(topic_id);
         let msg = PubsubMessage {
             data: data.into_bytes(),
             ..Default::default()
@@ -120,6 +545,37 @@ impl PubSubClient {
         }
     }
 
+    /// Check that `topic_id` exists and validates messages as binary Avro of `schema_id`.
+    pub async fn check_topic(
+        &self,
+        topic_id: &str,
+        schema_id: &str,
+    ) -> Result<(), String> {
+        let topic_request = Request::new(GetTopicRequest {
+            topic: self.client.topic(topic_id).fully_qualified_name().to_string(),
+        });
+
+        let topic = {
+            let mut client = self.topic_client.lock().await;
+            match client.get_topic(topic_request).await {
+                Ok(resp) => resp.into_inner(),
+                Err(e) => return Err(format!("Failed to get topic {}: {}", topic_id, e)),
+            }
+        };
+
+        let schema_settings = match topic.schema_settings {
+            Some(schema_settings) => schema_settings,
+            None => return Err(format!("Topic {} has no schema, expected {}", topic_id, schema_id)),
+        };
+        if schema_settings.schema.rsplit('/').next() != Some(schema_id) {
+            return Err(format!("Topic {} uses schema {}, expected {}", topic_id, schema_settings.schema, schema_id));
+        }
+        if schema_settings.encoding != Encoding::Binary as i32 {
+            return Err(format!("Topic {} does not use binary encoding, stats are published as binary Avro", topic_id));
+        }
+        Ok(())
+    }
+
     async fn fetch_schema_definition(
         &self,
         project_id: &str,
@@ -137,52 +593,144 @@ impl PubSubClient {
         }
     }
 
-    async fn get_schema(&self, schema_id: &str) -> Result<Schema, String> {
+    pub async fn get_schema(&self, schema_id: &str) -> Result<Schema, String> {
         let app_state = APP_STATE.get().unwrap();
 
         // Create cache key
-        let cache_key = format!("{}_pubsub_schema", schema_id);
+        let cache_key = app_state.cache_keys.key(&SCHEMA_CACHE_NAMESPACE, &[schema_id]).await;
 
-        // Try instance cache
-        if let Some(schema_str) = app_state.instance_cache.get::<String>(&cache_key) {
-            match Schema::parse_str(&schema_str) {
-                Ok(schema) => return Ok(schema),
-                Err(e) => tracing::error!("Failed to parse schema from instance cache - err: {}", e),
+        // Try instance cache, then Redis, then PubSub with a single fetch per schema
+        let options = CacheOptions::new(86400, 86400).redis_lock(SCHEMA_LOAD_LOCK_MS);
+        let schema_id = schema_id.to_string();
+        let schema_definition = app_state.tiered_cache.get_or_load(&cache_key, &options, move || async move {
+            let app_state = APP_STATE.get().unwrap();
+            match app_state.pubsub_client.fetch_schema_definition(
+                &app_state.config.google_cloud_project,
+                &schema_id
+            ).await {
+                Ok(schema_definition) => Ok(schema_definition),
+                Err(e) => Err(format!("Failed to fetch schema: {}", e)),
             }
-        }
+        }).await?;
 
-        // Try Redis cache
-        if let Ok(Some(schema_str)) = app_state.redis_client.get::<String>(&cache_key).await {
-            // Cache in instance cache
-            app_state.instance_cache.set(&cache_key, schema_str.clone(), 86400);
+        parse_schema(&schema_definition)
+    }
 
-            match Schema::parse_str(&schema_str) {
-                Ok(schema) => return Ok(schema),
-                Err(e) => tracing::error!("Failed to parse schema from Redis - err: {}", e),
+    /// Fetch `schema_id` from Pub/Sub, bypassing the caches.
+    pub async fn fetch_schema(&self, project_id: &str, schema_id: &str) -> Result<Schema, String> {
+        let schema_definition = self.fetch_schema_definition(project_id, schema_id).await?;
+        parse_schema(&schema_definition)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retry_config() -> PubSubRetryConfig {
+        PubSubRetryConfig {
+            max_attempts: 5,
+            initial_backoff_ms: 200,
+            max_backoff_ms: 1000,
+        }
+    }
+
+    #[test]
+    fn retry_backoff_doubles_with_jitter_up_to_max() {
+        let retry = retry_config();
+        for _ in 0..100 {
+            for (attempt, backoff_ms) in [(1, 200), (2, 400), (3, 800), (4, 1000), (40, 1000)] {
+                let backoff = retry_backoff(&retry, attempt).as_millis() as u64;
+                assert!(
+                    (backoff_ms / 2..=backoff_ms).contains(&backoff),
+                    "backoff {} after attempt {} not in {}..={}", backoff, attempt, backoff_ms / 2, backoff_ms
+                );
             }
         }
+    }
 
-        // Fetch directly from PubSub
-        match self.fetch_schema_definition(
-            &app_state.config.google_cloud_project,
-            schema_id
-        ).await {
-            Ok(schema_definition) => {
-                // Cache in Redis
-                match app_state.redis_client.set(cache_key.clone(), schema_definition.clone(), Some(86400)).await {
-                    Ok(_) => {},
-                    Err(e) => tracing::error!("Failed to set schema in Redis - err: {}", e),
-                }
+    #[test]
+    fn retry_backoff_handles_zero_backoff() {
+        let retry = PubSubRetryConfig { initial_backoff_ms: 0, ..retry_config() };
+        assert_eq!(retry_backoff(&retry, 1), Duration::ZERO);
+    }
 
-                // Cache in instance cache
-                app_state.instance_cache.set(&cache_key, schema_definition.clone(), 86400);
+    fn message(ordering_key: &str, data: &str) -> PubsubMessage {
+        PubsubMessage {
+            data: data.as_bytes().to_vec(),
+            ordering_key: ordering_key.to_string(),
+            ..Default::default()
+        }
+    }
 
-                match Schema::parse_str(&schema_definition) {
-                    Ok(schema) => return Ok(schema),
-                    Err(e) => return Err(format!("Failed to parse schema: {}", e)),
-                }
-            },
-            Err(e) => return Err(format!("Failed to fetch schema: {}", e)),
+    fn unavailable() -> Status {
+        Status::new(Code::Unavailable, "unavailable")
+    }
+
+    fn invalid() -> Status {
+        Status::new(Code::InvalidArgument, "invalid")
+    }
+
+    fn data(messages: &[PubsubMessage]) -> Vec<&str> {
+        messages.iter().map(|message| std::str::from_utf8(&message.data).unwrap()).collect()
+    }
+
+    #[test]
+    fn hold_back_follows_the_first_failure_of_a_key() {
+        let messages = [message("a", "a1"), message("a", "a2"), message("a", "a3"), message("b", "b1"), message("", "n1"), message("", "n2")];
+        let outcomes = hold_back_failed_keys(&messages, vec![
+            Ok("1".to_string()), Err(unavailable()), Err(unavailable()), Ok("4".to_string()), Err(unavailable()), Err(unavailable()),
+        ]);
+
+        let kinds = outcomes.iter()
+            .map(|outcome| match outcome {
+                Some(Ok(_)) => "published",
+                Some(Err(_)) => "failed",
+                None => "held back",
+            })
+            .collect::<Vec<_>>();
+        // Messages without ordering key fail independently
+        assert_eq!(kinds, ["published", "failed", "held back", "published", "failed", "failed"]);
+    }
+
+    #[test]
+    fn hold_back_keeps_published_messages() {
+        // Messages published after a failure of their key are already sent, they are not held back
+        let messages = [message("a", "a1"), message("a", "a2")];
+        let outcomes = hold_back_failed_keys(&messages, vec![Err(unavailable()), Ok("2".to_string())]);
+        assert!(matches!(outcomes[..], [Some(Err(_)), Some(Ok(_))]));
+    }
+
+    #[test]
+    fn held_back_messages_share_the_fate_of_their_key() {
+        let messages = vec![message("a", "a1"), message("a", "a2"), message("b", "b1"), message("b", "b2"), message("", "n1")];
+        let outcomes = vec![Some(Err(unavailable())), None, Some(Err(invalid())), None, Some(Err(unavailable()))];
+
+        let (retryable, failed, error) = partition_outcomes(messages.clone(), outcomes.clone(), true);
+        assert_eq!(data(&retryable), ["a1", "a2", "n1"]);
+        assert_eq!(data(&failed), ["b1", "b2"]);
+        assert!(error.unwrap().contains("unavailable"));
+
+        // Out of attempts, everything is given up in order
+        let (retryable, failed, _) = partition_outcomes(messages, outcomes, false);
+        assert!(retryable.is_empty());
+        assert_eq!(data(&failed), ["a1", "a2", "b1", "b2", "n1"]);
+    }
+
+    #[test]
+    fn published_messages_are_neither_retried_nor_failed() {
+        let messages = vec![message("a", "a1"), message("", "n1")];
+        let (retryable, failed, error) = partition_outcomes(messages, vec![Some(Ok("1".to_string())), Some(Ok("2".to_string()))], true);
+        assert!(retryable.is_empty() && failed.is_empty() && error.is_none());
+    }
+
+    #[test]
+    fn retries_only_transient_errors() {
+        for code in [Code::Unavailable, Code::DeadlineExceeded, Code::ResourceExhausted, Code::Aborted, Code::Internal, Code::Unknown, Code::Cancelled] {
+            assert!(is_retryable(&Status::new(code, "transient")), "{:?} should be retried", code);
+        }
+        for code in [Code::InvalidArgument, Code::NotFound, Code::PermissionDenied, Code::Unauthenticated, Code::FailedPrecondition, Code::Unimplemented] {
+            assert!(!is_retryable(&Status::new(code, "permanent")), "{:?} should not be retried", code);
         }
     }
 }
\ No newline at end of file
@@ -1,3 +1,14 @@
 pub mod pubsub_topic {
     pub const GENERAL_ERROR_TOPIC: &str = "general-error-topic";
+    pub const TEST_STATS: &str = "Stats_TestStats";
+}
+
+pub mod pubsub_schema {
+    pub const TEST_STATS: &str = "Stats_TestStats";
+}
+
+// Attributes set by Pub/Sub on messages of topics with a schema
+pub mod pubsub_attribute {
+    pub const SCHEMA_NAME: &str = "googclient_schemaname";
+    pub const SCHEMA_ENCODING: &str = "googclient_schemaencoding";
 }
\ No newline at end of file
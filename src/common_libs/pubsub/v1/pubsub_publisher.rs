@@ -1,105 +1,459 @@
+use serde::Serialize;
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 use std::time::Duration;
-use tokio::sync::{mpsc, Mutex};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
+use crate::config::{OverflowPolicy, PubSubConfig};
 use crate::state::APP_STATE;
-use super::models::StatRecord;
+use super::avro_parser::AvroParser;
+use super::models::{BoundStat, StatBinding, StatRecord};
+use super::stat_queue::StatQueue;
+
+// Pub/Sub publish request limits
+const PUBSUB_MAX_BATCH_MESSAGES: usize = 1000;
+const PUBSUB_MAX_BATCH_BYTES: usize = 10_000_000;
+// Protobuf framing of each message, on top of its data, attributes and ordering key
+const MESSAGE_OVERHEAD_BYTES: usize = 64;
 
 pub struct StatWithMetadata {
-    pub dataset_id: String,
-    pub table_id: String,
+    pub binding: &'static StatBinding,
     pub stat: Box<dyn StatRecord>,
 }
 
+impl StatWithMetadata {
+    fn topic_id(&self) -> String {
+        self.binding.topic.to_string()
+    }
+}
+
+// Stats buffered for one topic
+struct TopicBatch {
+    stats: Vec<StatWithMetadata>,
+    bytes: usize,
+    started_at: Instant,
+}
+
+// Count, size and age after which a batch is published, whichever is hit first
+struct BatchLimits {
+    max_messages: usize,
+    max_bytes: usize,
+    max_age: Duration,
+}
+
+impl BatchLimits {
+    fn new(config: &PubSubConfig) -> Self {
+        Self {
+            max_messages: config.max_messages.clamp(1, PUBSUB_MAX_BATCH_MESSAGES),
+            max_bytes: config.max_bytes.clamp(1, PUBSUB_MAX_BATCH_BYTES),
+            max_age: Duration::from_secs(config.max_latency),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PublishError {
+    // Shed by the overflow policy, handlers should answer 503
+    QueueFull,
+    // The publisher is shutting down
+    Closed,
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PublishError::QueueFull => write!(f, "Stat queue is full"),
+            PublishError::Closed => write!(f, "Stat queue is closed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PubSubPublisherStats {
+    pub overflow_policy: OverflowPolicy,
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    pub queued: u64,
+    pub dropped_newest: u64,
+    pub dropped_oldest: u64,
+    pub rejected: u64,
+}
+
 pub struct PubSubPublisher {
-    tx: Mutex<mpsc::Sender<StatWithMetadata>>,
-    rx: Mutex<mpsc::Receiver<StatWithMetadata>>,
-    stat_buffers: Mutex<HashMap<String, Vec<StatWithMetadata>>>,
+    queue: StatQueue,
+    overflow_policy: OverflowPolicy,
+    block_timeout: Duration,
+    // Batching task, publishes everything left once stopped
+    batching_tasks: TaskTracker,
+    // In-flight publish_batch tasks
+    publish_tasks: TaskTracker,
+    shutdown: CancellationToken,
+    // Cancelled at the shutdown deadline, publish tasks then spill what is left
+    deadline: CancellationToken,
+    // Per topic, cancelled once the last flushed batch with ordering keys is published
+    ordered_publishes: Mutex<HashMap<String, CancellationToken>>,
+    queued: AtomicU64,
+    dropped_newest: AtomicU64,
+    dropped_oldest: AtomicU64,
+    rejected: AtomicU64,
 }
 
 impl PubSubPublisher {
-    pub fn new() -> Self {
-        let (tx, rx) = mpsc::channel::<StatWithMetadata>(1000);
+    pub fn new(config: &PubSubConfig) -> Self {
         Self {
-            tx: Mutex::new(tx),
-            rx: Mutex::new(rx),
-            stat_buffers: Mutex::new(HashMap::new()),
+            queue: StatQueue::new(config.channel_capacity),
+            overflow_policy: config.overflow_policy,
+            block_timeout: Duration::from_millis(config.block_timeout_ms),
+            batching_tasks: TaskTracker::new(),
+            publish_tasks: TaskTracker::new(),
+            shutdown: CancellationToken::new(),
+            deadline: CancellationToken::new(),
+            ordered_publishes: Mutex::new(HashMap::new()),
+            queued: AtomicU64::new(0),
+            dropped_newest: AtomicU64::new(0),
+            dropped_oldest: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> PubSubPublisherStats {
+        PubSubPublisherStats {
+            overflow_policy: self.overflow_policy,
+            queue_depth: self.queue.len(),
+            queue_capacity: self.queue.capacity(),
+            queued: self.queued.load(Ordering::Relaxed),
+            dropped_newest: self.dropped_newest.load(Ordering::Relaxed),
+            dropped_oldest: self.dropped_oldest.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
         }
     }
 
+    /// Queue a stat for publishing, applying the overflow policy if the queue is full.
+    ///
+    /// Stats dropped by `drop_newest` / `drop_oldest` are counted, not reported as errors.
     pub async fn publish<T>(
         &self,
-        dataset_id: String,
-        table_id: String,
         stat: T,
-    ) -> Result<(), String>
+    ) -> Result<(), PublishError>
     where
-        T: StatRecord + 'static,
+        T: BoundStat,
     {
         let metadata = StatWithMetadata {
-            dataset_id,
-            table_id,
+            binding: T::BINDING,
             stat: Box::new(stat),
         };
-        let tx = self.tx.lock().await;
-        match tx.send(metadata).await {
-            Ok(_) => Ok(()),
-            Err(e) => Err(format!("Failed to send stat across message queue: {}", e)),
+
+        let result = match self.overflow_policy {
+            OverflowPolicy::Block => match tokio::time::timeout(self.block_timeout, self.queue.push(metadata)).await {
+                Ok(Ok(_)) => Ok(()),
+                Ok(Err(_)) => Err(PublishError::Closed),
+                Err(_) => {
+                    self.rejected.fetch_add(1, Ordering::Relaxed);
+                    Err(PublishError::QueueFull)
+                },
+            },
+            OverflowPolicy::DropNewest => match self.queue.try_push(metadata) {
+                Ok(_) => Ok(()),
+                Err(_) if self.shutdown.is_cancelled() => Err(PublishError::Closed),
+                Err(_) => {
+                    self.dropped_newest.fetch_add(1, Ordering::Relaxed);
+                    tracing::debug!("Stat queue full, dropped newest stat");
+                    return Ok(());
+                },
+            },
+            OverflowPolicy::DropOldest => match self.queue.push_evicting(metadata) {
+                Ok(None) => Ok(()),
+                Ok(Some(evicted)) => {
+                    self.dropped_oldest.fetch_add(1, Ordering::Relaxed);
+                    tracing::debug!("Stat queue full, dropped oldest stat for {}", evicted.binding.topic);
+                    Ok(())
+                },
+                Err(_) => Err(PublishError::Closed),
+            },
+            OverflowPolicy::Reject => match self.queue.try_push(metadata) {
+                Ok(_) => Ok(()),
+                Err(_) if self.shutdown.is_cancelled() => Err(PublishError::Closed),
+                Err(_) => {
+                    self.rejected.fetch_add(1, Ordering::Relaxed);
+                    Err(PublishError::QueueFull)
+                },
+            },
+        };
+
+        if result.is_ok() {
+            self.queued.fetch_add(1, Ordering::Relaxed);
         }
+        result
     }
 
     pub fn start_stats_processing_tasks() {
-        // Task for processing messages in batches based on count or size
-        tokio::spawn(async move {
-            Self::process_stats_in_sized_batches().await;
-        });
+        let publisher = &APP_STATE.get().unwrap().pubsub_publisher;
 
-        // Task for processing messages based on time
-        tokio::spawn(async move {
-            Self::process_stats_in_timed_batches().await;
+        // Task batching stats per topic, by count, size and age
+        publisher.batching_tasks.spawn(async move {
+            Self::process_stats_in_batches().await;
         });
     }
 
-    async fn process_stats_in_sized_batches() {
+    async fn process_stats_in_batches() {
         let app_state = APP_STATE.get().unwrap();
-        let mut rx = app_state.pubsub_publisher.rx.lock().await;
-        while let Some(stat_with_metadata) = rx.recv().await {
-            let topic_id = format!("{}_{}", &stat_with_metadata.dataset_id, &stat_with_metadata.table_id);
-
-            let mut buffers = app_state.pubsub_publisher.stat_buffers.lock().await;
-            let batch = buffers.entry(topic_id.clone()).or_insert_with(Vec::new);
-            batch.push(stat_with_metadata);
-
-            // Calculate total data bytes
-            let total_data_bytes = batch.iter()
-                .map(|item| item.stat.data_len())
-                .sum::<usize>();
-
-            if batch.len() >= app_state.config.pubsub.max_messages || total_data_bytes >= app_state.config.pubsub.max_bytes {
-                let to_publish = std::mem::take(batch);
-                let topic_clone = topic_id.clone();
-                tokio::spawn(async move {
-                    app_state.pubsub_client.publish_batch(&topic_clone, to_publish).await;
-                });
+        let publisher = &app_state.pubsub_publisher;
+        let limits = BatchLimits::new(&app_state.config.pubsub);
+        let mut batches: HashMap<String, TopicBatch> = HashMap::new();
+
+        loop {
+            let next_deadline = batches.values()
+                .map(|batch| batch.started_at + limits.max_age)
+                .min();
+            let expired = async {
+                match next_deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            let stat_with_metadata = tokio::select! {
+                stat_with_metadata = publisher.queue.pop() => match stat_with_metadata {
+                    Some(stat_with_metadata) => stat_with_metadata,
+                    None => break,
+                },
+                _ = expired => {
+                    let now = Instant::now();
+                    let expired_topics = batches.iter()
+                        .filter(|(_, batch)| batch.started_at + limits.max_age <= now)
+                        .map(|(topic_id, _)| topic_id.clone())
+                        .collect::<Vec<_>>();
+                    for topic_id in expired_topics {
+                        Self::flush(&mut batches, &topic_id);
+                    }
+                    continue;
+                },
+                _ = publisher.shutdown.cancelled() => break,
+            };
+            Self::add_to_batch(&mut batches, &limits, stat_with_metadata);
+        }
+
+        // Shutting down, the queue is closed
+        while let Some(stat_with_metadata) = publisher.queue.try_pop() {
+            Self::add_to_batch(&mut batches, &limits, stat_with_metadata);
+        }
+        let topic_ids = batches.keys().cloned().collect::<Vec<_>>();
+        for topic_id in topic_ids {
+            Self::flush(&mut batches, &topic_id);
+        }
+    }
+
+    fn add_to_batch(
+        batches: &mut HashMap<String, TopicBatch>,
+        limits: &BatchLimits,
+        stat_with_metadata: StatWithMetadata,
+    ) {
+        let topic_id = stat_with_metadata.topic_id();
+        let metadata_bytes = stat_with_metadata.stat.attributes().iter()
+            .map(|(key, value)| key.len() + value.len())
+            .sum::<usize>()
+            + stat_with_metadata.stat.ordering_key().map(|key| key.len()).unwrap_or_default();
+        let bytes = match AvroParser.estimate_encoded_len(&stat_with_metadata.stat) {
+            Ok(bytes) => bytes + metadata_bytes + MESSAGE_OVERHEAD_BYTES,
+            Err(e) => {
+                tracing::warn!("Could not estimate stat size for {} - err: {}", topic_id, e);
+                metadata_bytes + MESSAGE_OVERHEAD_BYTES
             }
+        };
+
+        // Publish what is buffered first if the stat would not fit in the request
+        let overflows = batches.get(&topic_id)
+            .is_some_and(|batch| batch.bytes + bytes > limits.max_bytes);
+        if overflows {
+            Self::flush(batches, &topic_id);
+        }
+
+        let batch = batches.entry(topic_id.clone()).or_insert_with(|| TopicBatch {
+            stats: Vec::new(),
+            bytes: 0,
+            started_at: Instant::now(),
+        });
+        batch.stats.push(stat_with_metadata);
+        batch.bytes += bytes;
+
+        if batch.stats.len() >= limits.max_messages || batch.bytes >= limits.max_bytes {
+            Self::flush(batches, &topic_id);
         }
     }
 
-    async fn process_stats_in_timed_batches() {
-        loop {
-            let app_state: &std::sync::Arc<crate::state::AppState> = APP_STATE.get().unwrap();
-            tokio::time::sleep(Duration::from_secs(app_state.config.pubsub.max_latency)).await;
-            let mut buffers = app_state.pubsub_publisher.stat_buffers.lock().await;
-
-            for (topic, batch) in buffers.iter_mut() {
-                if batch.len() > 0 {
-                    let to_publish = std::mem::take(batch);
-                    let topic_clone = topic.clone();
-                    tokio::spawn(async move {
-                        app_state.pubsub_client.publish_batch(&topic_clone, to_publish).await;
-                    });
+    // Token to cancel once the next ordered batch of `topic_id` is published, and the one
+    // of the batch before it, to wait for
+    fn chain_ordered_publish(&self, topic_id: &str) -> (Option<CancellationToken>, CancellationToken) {
+        let published = CancellationToken::new();
+        let previous = self.ordered_publishes.lock().unwrap().insert(topic_id.to_string(), published.clone());
+        (previous, published)
+    }
+
+    fn flush(batches: &mut HashMap<String, TopicBatch>, topic_id: &str) {
+        let app_state = APP_STATE.get().unwrap();
+        let batch = match batches.remove(topic_id) {
+            Some(batch) => batch,
+            None => return,
+        };
+
+        // Batches with ordering keys are published one after the other per topic, in flush order
+        let publisher = &app_state.pubsub_publisher;
+        let is_ordered = batch.stats.iter().any(|stat_with_metadata| stat_with_metadata.stat.ordering_key().is_some());
+        let (previous, published) = match is_ordered {
+            true => {
+                let (previous, published) = publisher.chain_ordered_publish(topic_id);
+                (previous, Some(published))
+            },
+            false => (None, None),
+        };
+
+        let topic_id = topic_id.to_string();
+        publisher.publish_tasks.spawn(async move {
+            let _published = published.map(|published| published.drop_guard());
+            let deadline = &app_state.pubsub_publisher.deadline;
+            if let Some(previous) = previous {
+                tokio::select! {
+                    _ = previous.cancelled() => {},
+                    _ = deadline.cancelled() => {},
                 }
             }
+            app_state.pubsub_client.publish_batch(&topic_id, batch.stats, deadline).await;
+        });
+    }
+
+    /// Stop batching, publish everything still queued or buffered and wait for
+    /// in-flight publishes until `deadline`, then spill what is not published.
+    pub async fn shutdown(deadline: Instant) {
+        let app_state = APP_STATE.get().unwrap();
+        let publisher = &app_state.pubsub_publisher;
+
+        // The batching task drains the queue and flushes every batch before it stops
+        publisher.shutdown.cancel();
+        publisher.queue.close();
+        publisher.batching_tasks.close();
+
+        let flushed = async {
+            publisher.batching_tasks.wait().await;
+            publisher.publish_tasks.close();
+            tracing::info!("Flushing stats, waiting for {} publish tasks", publisher.publish_tasks.len());
+            publisher.publish_tasks.wait().await;
+            app_state.pubsub_client.shutdown_publishers().await;
+        };
+        if tokio::time::timeout_at(deadline, flushed).await.is_ok() {
+            tracing::info!("Flushed stats");
+            return;
+        }
+
+        // Messages sent but not confirmed yet are spilled too, replays may duplicate them
+        tracing::error!("Stats flush reached the shutdown deadline, spilling {} in-flight batches", publisher.publish_tasks.len());
+        publisher.deadline.cancel();
+        publisher.batching_tasks.wait().await;
+        publisher.publish_tasks.close();
+        publisher.publish_tasks.wait().await;
+        tracing::info!("Spilled in-flight stats");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::common_libs::pubsub::v1::models::test_stats::TestStats;
+
+    fn publisher(overflow_policy: OverflowPolicy) -> PubSubPublisher {
+        let mut config = AppConfig::default().pubsub;
+        config.channel_capacity = 1;
+        config.overflow_policy = overflow_policy;
+        config.block_timeout_ms = 10;
+        PubSubPublisher::new(&config)
+    }
+
+    fn stat(event_type: &str) -> TestStats {
+        TestStats { event_type: event_type.to_string(), ..Default::default() }
+    }
+
+    fn queued_event_type(publisher: &PubSubPublisher) -> Option<String> {
+        publisher.queue.try_pop().and_then(|item| item.stat.attributes().remove("event_type"))
+    }
+
+    #[tokio::test]
+    async fn block_rejects_after_timeout() {
+        let publisher = publisher(OverflowPolicy::Block);
+        assert_eq!(publisher.publish(stat("a")).await, Ok(()));
+        assert_eq!(publisher.publish(stat("b")).await, Err(PublishError::QueueFull));
+
+        let stats = publisher.stats();
+        assert_eq!((stats.queued, stats.rejected, stats.queue_depth), (1, 1, 1));
+        assert_eq!(queued_event_type(&publisher).as_deref(), Some("a"));
+    }
+
+    #[tokio::test]
+    async fn drop_newest_keeps_queued_stats() {
+        let publisher = publisher(OverflowPolicy::DropNewest);
+        assert_eq!(publisher.publish(stat("a")).await, Ok(()));
+        assert_eq!(publisher.publish(stat("b")).await, Ok(()));
+
+        let stats = publisher.stats();
+        assert_eq!((stats.queued, stats.dropped_newest, stats.rejected), (1, 1, 0));
+        assert_eq!(queued_event_type(&publisher).as_deref(), Some("a"));
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_new_stats() {
+        let publisher = publisher(OverflowPolicy::DropOldest);
+        assert_eq!(publisher.publish(stat("a")).await, Ok(()));
+        assert_eq!(publisher.publish(stat("b")).await, Ok(()));
+
+        let stats = publisher.stats();
+        assert_eq!((stats.queued, stats.dropped_oldest, stats.rejected), (2, 1, 0));
+        assert_eq!(queued_event_type(&publisher).as_deref(), Some("b"));
+    }
+
+    #[tokio::test]
+    async fn reject_fails_when_full() {
+        let publisher = publisher(OverflowPolicy::Reject);
+        assert_eq!(publisher.publish(stat("a")).await, Ok(()));
+        assert_eq!(publisher.publish(stat("b")).await, Err(PublishError::QueueFull));
+
+        let stats = publisher.stats();
+        assert_eq!((stats.queued, stats.rejected), (1, 1));
+        assert_eq!(queued_event_type(&publisher).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn ordered_publishes_chain_per_topic() {
+        let publisher = publisher(OverflowPolicy::Block);
+        let (previous, first) = publisher.chain_ordered_publish("topic_a");
+        assert!(previous.is_none());
+        let (previous, second) = publisher.chain_ordered_publish("topic_a");
+        let (other_topic, _) = publisher.chain_ordered_publish("topic_b");
+        assert!(other_topic.is_none());
+
+        // The second batch waits for the first one only
+        let previous = previous.unwrap();
+        assert!(!previous.is_cancelled());
+        drop(first.drop_guard());
+        assert!(previous.is_cancelled());
+
+        let (previous, _) = publisher.chain_ordered_publish("topic_a");
+        assert!(!previous.as_ref().unwrap().is_cancelled());
+        second.cancel();
+        assert!(previous.unwrap().is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn closed_publisher_fails_under_every_policy() {
+        for overflow_policy in [OverflowPolicy::Block, OverflowPolicy::DropNewest, OverflowPolicy::DropOldest, OverflowPolicy::Reject] {
+            let publisher = publisher(overflow_policy);
+            publisher.shutdown.cancel();
+            publisher.queue.close();
+
+            assert_eq!(publisher.publish(stat("a")).await, Err(PublishError::Closed), "{:?}", overflow_policy);
+            assert_eq!(publisher.stats().queued, 0);
         }
     }
 }
\ No newline at end of file
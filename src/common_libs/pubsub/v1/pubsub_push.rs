@@ -0,0 +1,175 @@
+use axum::extract::{FromRequest, FromRequestParts, Query, Request};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode, Uri};
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::routing::{post, MethodRouter};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::common_libs::utils::security_headers::v1::add_headers;
+use crate::state::APP_STATE;
+use super::pubsub_push_auth::verify_push_request;
+use super::pubsub_subscriber::{MessageContext, MessageHandler};
+
+// Query parameter carrying the token configured in the push endpoint URL
+const PUSH_TOKEN_PARAM: &str = "token";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PushEnvelope {
+    message: PushMessage,
+    subscription: String,
+    #[serde(default)]
+    delivery_attempt: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PushMessage {
+    #[serde(default)]
+    data: String,
+    #[serde(default)]
+    attributes: HashMap<String, String>,
+    message_id: String,
+    #[serde(default)]
+    publish_time: Option<DateTime<Utc>>,
+    #[serde(default)]
+    ordering_key: String,
+}
+
+/// Message of a Pub/Sub push request, decoded like pulled messages.
+///
+/// If `pubsub.push_auth` is configured, requests must carry the OIDC token of
+/// the push subscription. If a push token is configured, requests without a
+/// matching `token` query parameter are rejected. Prod requires one of them.
+#[allow(dead_code)]
+pub struct PubSubPush<T> {
+    pub message: T,
+    pub context: MessageContext,
+}
+
+impl<S, T> FromRequest<S> for PubSubPush<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let app_state = APP_STATE.get().unwrap();
+
+        authorize_push(req.headers(), req.uri()).await?;
+
+        let Json(envelope) = match Json::<PushEnvelope>::from_request(req, state).await {
+            Ok(envelope) => envelope,
+            Err(err) => {
+                tracing::error!("Invalid push envelope - err: {}", err);
+                return Err((StatusCode::BAD_REQUEST, format!("Invalid push envelope: {}", err)));
+            }
+        };
+
+        let data = match STANDARD.decode(&envelope.message.data) {
+            Ok(data) => data,
+            Err(err) => {
+                tracing::error!("Invalid push message data {} - err: {}", envelope.message.message_id, err);
+                return Err((StatusCode::BAD_REQUEST, format!("Invalid push message data: {}", err)));
+            }
+        };
+
+        let message = match app_state.pubsub_client.decode_message::<T>(&data, &envelope.message.attributes).await {
+            Ok(message) => message,
+            Err(err) => {
+                tracing::error!("Could not decode push message {} - err: {}", envelope.message.message_id, err);
+                return Err((StatusCode::BAD_REQUEST, format!("Could not decode push message: {}", err)));
+            }
+        };
+
+        Ok(Self {
+            message,
+            context: MessageContext {
+                subscription: envelope.subscription,
+                message_id: envelope.message.message_id,
+                publish_time: envelope.message.publish_time,
+                attributes: envelope.message.attributes,
+                ordering_key: envelope.message.ordering_key,
+                delivery_attempt: envelope.delivery_attempt,
+            },
+        })
+    }
+}
+
+/// Check the OIDC token and the push token configured in `pubsub`, if any.
+async fn authorize_push(headers: &HeaderMap, uri: &Uri) -> Result<(), (StatusCode, String)> {
+    let app_state = APP_STATE.get().unwrap();
+
+    let push_auth = &app_state.config.pubsub.push_auth;
+    let authorized = match push_auth.is_enabled() {
+        true => verify_push_request(headers, push_auth).await,
+        false => Ok(()),
+    };
+    if let Err(e) = authorized {
+        tracing::warn!("Rejected push request - err: {}", e);
+        return Err((StatusCode::UNAUTHORIZED, "Invalid push authorization".to_string()));
+    }
+
+    let expected_token = app_state.config.pubsub.push_verification_token.as_bytes();
+    if !expected_token.is_empty() {
+        let Query(query) = Query::<HashMap<String, String>>::try_from_uri(uri)
+            .unwrap_or(Query(HashMap::new()));
+        let token = query.get(PUSH_TOKEN_PARAM).map(|token| token.as_bytes()).unwrap_or_default();
+        if !tokens_match(token, expected_token) {
+            tracing::warn!("Rejected push request with invalid token");
+            return Err((StatusCode::UNAUTHORIZED, "Invalid push token".to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Extractor rejecting requests not authorized like push requests, for
+/// internal endpoints called by Pub/Sub or Cloud Scheduler.
+pub struct PushAuthorized;
+
+impl<S> FromRequestParts<S> for PushAuthorized
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, String);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        authorize_push(&parts.headers, &parts.uri).await?;
+        Ok(Self)
+    }
+}
+
+// Constant-time comparison, so that the token cannot be guessed from response times
+fn tokens_match(token: &[u8], expected: &[u8]) -> bool {
+    token.len() == expected.len()
+        && token.iter().zip(expected).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Route for a push subscription endpoint.
+///
+/// Pub/Sub acks the message on a 2xx response and redelivers it on any other,
+/// including rejected requests.
+#[allow(dead_code)]
+pub fn push_route<H: MessageHandler>(handler: H) -> MethodRouter {
+    let handler = Arc::new(handler);
+    post(move |push: PubSubPush<H::Message>| {
+        let handler = handler.clone();
+        async move {
+            let security_headers = add_headers();
+            match handler.handle(push.message, &push.context).await {
+                Ok(_) => (StatusCode::NO_CONTENT, security_headers).into_response(),
+                Err(e) => {
+                    tracing::warn!("Push message {} from {} failed, nacked for redelivery - err: {}", push.context.message_id, push.context.subscription, e);
+                    (StatusCode::INTERNAL_SERVER_ERROR, security_headers).into_response()
+                }
+            }
+        }
+    })
+}
\ No newline at end of file
@@ -0,0 +1,307 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use google_cloud_googleapis::pubsub::v1::PubsubMessage;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::config::SpillTarget;
+use crate::state::APP_STATE;
+
+const SPILL_EXTENSION: &str = ".json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpillEncoding {
+    // Binary Avro of the topic schema, published as is
+    Avro,
+    // JSON of the stat, encoded with the topic schema on replay
+    Json,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SpilledMessage {
+    // Base64 encoded
+    pub data: String,
+    #[serde(default)]
+    pub attributes: HashMap<String, String>,
+    #[serde(default)]
+    pub ordering_key: String,
+}
+
+impl SpilledMessage {
+    pub fn from_pubsub(message: &PubsubMessage) -> Self {
+        Self {
+            data: STANDARD.encode(&message.data),
+            attributes: message.attributes.clone(),
+            ordering_key: message.ordering_key.clone(),
+        }
+    }
+
+    pub fn decode_data(&self) -> Result<Vec<u8>, String> {
+        match STANDARD.decode(&self.data) {
+            Ok(data) => Ok(data),
+            Err(e) => Err(format!("Failed to decode spilled message data: {}", e)),
+        }
+    }
+}
+
+/// Messages of a batch which could not be published, stored for replay.
+///
+/// Stored as one JSON file or GCS object per batch, depending on `pubsub.spill`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SpilledBatch {
+    pub topic: String,
+    // Empty in batches spilled before stats were bound to a schema, whose id was the topic
+    #[serde(default)]
+    pub schema_id: String,
+    pub encoding: SpillEncoding,
+    pub error: String,
+    pub spilled_at: String,
+    pub messages: Vec<SpilledMessage>,
+}
+
+impl SpilledBatch {
+    pub fn new(topic: &str, schema_id: &str, encoding: SpillEncoding, error: &str, messages: Vec<SpilledMessage>) -> Self {
+        Self {
+            topic: topic.to_string(),
+            schema_id: schema_id.to_string(),
+            encoding,
+            error: error.to_string(),
+            spilled_at: chrono::Utc::now().to_rfc3339(),
+            messages,
+        }
+    }
+
+    pub fn schema_id(&self) -> &str {
+        match self.schema_id.is_empty() {
+            true => &self.topic,
+            false => &self.schema_id,
+        }
+    }
+
+    /// Unique name for a new spill of this batch.
+    pub fn new_name(&self) -> String {
+        format!(
+            "{}.{}.{:08x}{}",
+            self.topic,
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.3f"),
+            rand::random::<u32>(),
+            SPILL_EXTENSION,
+        )
+    }
+
+    pub async fn save(&self, name: &str) -> Result<(), String> {
+        let app_state = APP_STATE.get().unwrap();
+        let config = &app_state.config.pubsub.spill;
+
+        match config.target {
+            SpillTarget::Disk => self.save_to_disk(&config.directory, name).await,
+            SpillTarget::Gcs => {
+                app_state.gcs_client.upload_json_to_gcs(
+                    config.bucket.clone(),
+                    format!("{}/{}", config.prefix, name),
+                    self,
+                ).await
+            },
+        }
+    }
+
+    pub async fn load(name: &str) -> Result<Self, String> {
+        let app_state = APP_STATE.get().unwrap();
+        let config = &app_state.config.pubsub.spill;
+
+        match config.target {
+            SpillTarget::Disk => Self::load_from_disk(&config.directory, name).await,
+            SpillTarget::Gcs => {
+                let object = format!("{}/{}", config.prefix, name);
+                match app_state.gcs_client.read_json_from_gcs::<Self>(&config.bucket, &object).await? {
+                    Some(batch) => Ok(batch),
+                    None => Err(format!("Spilled batch {} not found", name)),
+                }
+            },
+        }
+    }
+
+    pub async fn remove(name: &str) -> Result<(), String> {
+        let app_state = APP_STATE.get().unwrap();
+        let config = &app_state.config.pubsub.spill;
+
+        match config.target {
+            SpillTarget::Disk => Self::remove_from_disk(&config.directory, name).await,
+            SpillTarget::Gcs => {
+                app_state.gcs_client.delete_object(&config.bucket, &format!("{}/{}", config.prefix, name)).await
+            },
+        }
+    }
+
+    /// Names of all spilled batches, oldest first per topic.
+    pub async fn list() -> Result<Vec<String>, String> {
+        let app_state = APP_STATE.get().unwrap();
+        let config = &app_state.config.pubsub.spill;
+
+        let mut names = match config.target {
+            SpillTarget::Disk => Self::list_on_disk(&config.directory).await?,
+            SpillTarget::Gcs => {
+                let prefix = format!("{}/", config.prefix);
+                app_state.gcs_client.list_objects(&config.bucket, &prefix).await?
+                    .into_iter()
+                    .filter_map(|object| object.strip_prefix(&prefix).map(|name| name.to_string()))
+                    .collect()
+            },
+        };
+
+        names.retain(|name| name.ends_with(SPILL_EXTENSION));
+        names.sort();
+        Ok(names)
+    }
+
+    async fn save_to_disk(&self, directory: &str, name: &str) -> Result<(), String> {
+        let data = match serde_json::to_vec(self) {
+            Ok(data) => data,
+            Err(e) => return Err(format!("Failed to serialize spilled batch: {}", e)),
+        };
+        if let Err(e) = tokio::fs::create_dir_all(directory).await {
+            return Err(format!("Failed to create spill directory {}: {}", directory, e));
+        }
+
+        // Written aside and renamed, so that replays never read a partial file
+        let path = Path::new(directory).join(name);
+        let temp_path = path.with_extension("tmp");
+        if let Err(e) = tokio::fs::write(&temp_path, data).await {
+            return Err(format!("Failed to write spill file {}: {}", temp_path.display(), e));
+        }
+        match tokio::fs::rename(&temp_path, &path).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Failed to write spill file {}: {}", path.display(), e)),
+        }
+    }
+
+    async fn load_from_disk(directory: &str, name: &str) -> Result<Self, String> {
+        let path = Path::new(directory).join(name);
+        let data = match tokio::fs::read(&path).await {
+            Ok(data) => data,
+            Err(e) => return Err(format!("Failed to read spill file {}: {}", path.display(), e)),
+        };
+        match serde_json::from_slice::<Self>(&data) {
+            Ok(batch) => Ok(batch),
+            Err(e) => Err(format!("Failed to parse spill file {}: {}", path.display(), e)),
+        }
+    }
+
+    async fn remove_from_disk(directory: &str, name: &str) -> Result<(), String> {
+        let path = Path::new(directory).join(name);
+        match tokio::fs::remove_file(&path).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Failed to remove spill file {}: {}", path.display(), e)),
+        }
+    }
+
+    async fn list_on_disk(directory: &str) -> Result<Vec<String>, String> {
+        let mut entries = match tokio::fs::read_dir(directory).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read spill directory {}: {}", directory, e)),
+        };
+        let mut names = Vec::new();
+        loop {
+            match entries.next_entry().await {
+                Ok(Some(entry)) => names.push(entry.file_name().to_string_lossy().to_string()),
+                Ok(None) => break,
+                Err(e) => return Err(format!("Failed to read spill directory {}: {}", directory, e)),
+            }
+        }
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(messages: Vec<SpilledMessage>) -> SpilledBatch {
+        SpilledBatch::new("test_stats", "test_stats_schema", SpillEncoding::Avro, "Failed to publish", messages)
+    }
+
+    fn spill_directory() -> String {
+        std::env::temp_dir()
+            .join(format!("pubsub_spill_test_{:08x}", rand::random::<u32>()))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn spilled_message_round_trip() {
+        let message = PubsubMessage {
+            data: vec![0, 1, 2, 255],
+            attributes: HashMap::from([("schema_id".to_string(), "test_stats_schema".to_string())]),
+            ordering_key: "user-1".to_string(),
+            ..Default::default()
+        };
+        let spilled = SpilledMessage::from_pubsub(&message);
+
+        assert_eq!(spilled.decode_data(), Ok(message.data));
+        assert_eq!(spilled.attributes, message.attributes);
+        assert_eq!(spilled.ordering_key, message.ordering_key);
+        assert!(SpilledMessage { data: "not base64!".to_string(), ..spilled }.decode_data().is_err());
+    }
+
+    #[test]
+    fn schema_id_falls_back_to_topic() {
+        let mut batch = batch(Vec::new());
+        assert_eq!(batch.schema_id(), "test_stats_schema");
+
+        batch.schema_id.clear();
+        assert_eq!(batch.schema_id(), "test_stats");
+    }
+
+    #[tokio::test]
+    async fn disk_round_trip() {
+        let directory = spill_directory();
+        assert_eq!(SpilledBatch::list_on_disk(&directory).await, Ok(Vec::new()));
+
+        let message = PubsubMessage {
+            data: b"stat".to_vec(),
+            ordering_key: "user-1".to_string(),
+            ..Default::default()
+        };
+        let spilled = batch(vec![SpilledMessage::from_pubsub(&message)]);
+        let name = spilled.new_name();
+        assert!(name.starts_with("test_stats.") && name.ends_with(SPILL_EXTENSION));
+        spilled.save_to_disk(&directory, &name).await.unwrap();
+
+        // No temporary file is left next to the batch
+        assert_eq!(SpilledBatch::list_on_disk(&directory).await, Ok(vec![name.clone()]));
+
+        let loaded = SpilledBatch::load_from_disk(&directory, &name).await.unwrap();
+        assert_eq!(loaded.topic, spilled.topic);
+        assert_eq!(loaded.schema_id, spilled.schema_id);
+        assert_eq!(loaded.encoding, SpillEncoding::Avro);
+        assert_eq!(loaded.error, spilled.error);
+        assert_eq!(loaded.spilled_at, spilled.spilled_at);
+        assert_eq!(loaded.messages.len(), 1);
+        assert_eq!(loaded.messages[0].decode_data(), Ok(b"stat".to_vec()));
+        assert_eq!(loaded.messages[0].ordering_key, "user-1");
+
+        SpilledBatch::remove_from_disk(&directory, &name).await.unwrap();
+        assert_eq!(SpilledBatch::list_on_disk(&directory).await, Ok(Vec::new()));
+        assert!(SpilledBatch::load_from_disk(&directory, &name).await.is_err());
+
+        tokio::fs::remove_dir(&directory).await.unwrap();
+    }
+
+    #[test]
+    fn batches_without_schema_id_still_parse() {
+        let batch = serde_json::from_str::<SpilledBatch>(r#"{
+            "topic": "test_stats",
+            "encoding": "json",
+            "error": "Failed to publish",
+            "spilled_at": "2025-01-01T00:00:00+00:00",
+            "messages": [{"data": "e30="}]
+        }"#).unwrap();
+
+        assert_eq!(batch.schema_id(), "test_stats");
+        assert_eq!(batch.encoding, SpillEncoding::Json);
+        assert_eq!(batch.messages[0].decode_data(), Ok(b"{}".to_vec()));
+        assert!(batch.messages[0].attributes.is_empty());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,223 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use google_cloud_pubsub::subscriber::{ReceivedMessage, SubscriberConfig};
+use google_cloud_pubsub::subscription::SubscribeConfig;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::pin::pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+use crate::state::APP_STATE;
+
+const ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Delivery metadata of a received message.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct MessageContext {
+    pub subscription: String,
+    pub message_id: String,
+    pub publish_time: Option<DateTime<Utc>>,
+    pub attributes: HashMap<String, String>,
+    pub ordering_key: String,
+    // Only set if the subscription has a dead-letter policy
+    pub delivery_attempt: Option<usize>,
+}
+
+#[async_trait]
+pub trait MessageHandler: Send + Sync + 'static {
+    type Message: DeserializeOwned + Send + 'static;
+
+    /// Process one message. Ok acks it, on error it is nacked and redelivered.
+    async fn handle(&self, message: Self::Message, context: &MessageContext) -> Result<(), String>;
+}
+
+/// Streaming pull worker for a Pub/Sub subscription.
+///
+/// Messages are decoded with `PubSubClient::decode_message`. Their ack deadline
+/// is extended while the handler runs, for at most `max_ack_extension_secs`.
+/// Messages which cannot be decoded are nacked, so that the subscription's
+/// dead-letter policy applies.
+pub struct SubscriptionWorker<H: MessageHandler> {
+    subscription: String,
+    handler: H,
+    concurrency: usize,
+    max_outstanding_messages: i64,
+    max_outstanding_bytes: i64,
+    ack_deadline_secs: i32,
+    max_ack_extension_secs: u64,
+}
+
+#[allow(dead_code)]
+impl<H: MessageHandler> SubscriptionWorker<H> {
+    pub fn new(subscription: &str, handler: H) -> Self {
+        Self {
+            subscription: subscription.to_string(),
+            handler,
+            concurrency: 10,
+            max_outstanding_messages: 100,
+            max_outstanding_bytes: 100 * 1024 * 1024,
+            ack_deadline_secs: 60,
+            max_ack_extension_secs: 3600,
+        }
+    }
+
+    /// Set the maximum number of messages handled at once.
+    ///
+    /// Defaults to 10.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Set the number of unacked messages after which the server stops sending more.
+    ///
+    /// Defaults to 100.
+    pub fn max_outstanding_messages(mut self, max_outstanding_messages: i64) -> Self {
+        self.max_outstanding_messages = max_outstanding_messages;
+        self
+    }
+
+    /// Set the size of unacked messages after which the server stops sending more.
+    ///
+    /// Defaults to 100 MB.
+    pub fn max_outstanding_bytes(mut self, max_outstanding_bytes: i64) -> Self {
+        self.max_outstanding_bytes = max_outstanding_bytes;
+        self
+    }
+
+    /// Set the ack deadline requested for the stream and for each extension, between 10 and 600.
+    ///
+    /// Defaults to 60 seconds.
+    pub fn ack_deadline_secs(mut self, ack_deadline_secs: i32) -> Self {
+        self.ack_deadline_secs = ack_deadline_secs.clamp(10, 600);
+        self
+    }
+
+    /// Set how long the ack deadline of a message is extended while it is being handled.
+    ///
+    /// Defaults to 3600 seconds.
+    pub fn max_ack_extension_secs(mut self, max_ack_extension_secs: u64) -> Self {
+        self.max_ack_extension_secs = max_ack_extension_secs;
+        self
+    }
+
+    pub fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            Arc::new(self).run().await;
+        })
+    }
+
+    async fn run(self: Arc<Self>) {
+        let app_state = APP_STATE.get().unwrap();
+        let subscription = app_state.pubsub_client.subscription(&self.subscription);
+        let subscribe_config = SubscribeConfig::default()
+            .with_subscriber_config(SubscriberConfig {
+                stream_ack_deadline_seconds: self.ack_deadline_secs,
+                max_outstanding_messages: self.max_outstanding_messages,
+                max_outstanding_bytes: self.max_outstanding_bytes,
+                ..Default::default()
+            });
+        let in_flight = Arc::new(Semaphore::new(self.concurrency));
+
+        loop {
+            let mut stream = match subscription.subscribe(Some(subscribe_config.clone())).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::error!("Could not subscribe to {} - err: {}", self.subscription, e);
+                    tokio::time::sleep(ERROR_BACKOFF).await;
+                    continue;
+                }
+            };
+
+            loop {
+                let permit = in_flight.clone().acquire_owned().await.unwrap();
+                let received = match stream.read().await {
+                    Some(received) => received,
+                    None => break,
+                };
+
+                let worker = self.clone();
+                tokio::spawn(async move {
+                    worker.process(received).await;
+                    drop(permit);
+                });
+            }
+
+            tracing::warn!("Subscription stream of {} ended, resubscribing", self.subscription);
+            tokio::time::sleep(ERROR_BACKOFF).await;
+        }
+    }
+
+    async fn process(&self, received: ReceivedMessage) {
+        let app_state = APP_STATE.get().unwrap();
+        let context = MessageContext {
+            subscription: self.subscription.clone(),
+            message_id: received.message.message_id.clone(),
+            publish_time: received.message.publish_time.as_ref()
+                .and_then(|time| DateTime::from_timestamp(time.seconds, time.nanos.max(0) as u32)),
+            attributes: received.message.attributes.clone(),
+            ordering_key: received.message.ordering_key.clone(),
+            delivery_attempt: received.delivery_attempt(),
+        };
+
+        let message = match app_state.pubsub_client.decode_message::<H::Message>(
+            &received.message.data,
+            &received.message.attributes,
+        ).await {
+            Ok(message) => message,
+            Err(e) => {
+                tracing::error!("Could not decode message {} from {} - err: {}", context.message_id, self.subscription, e);
+                if let Err(e) = received.nack().await {
+                    tracing::error!("Could not nack message {} - err: {}", context.message_id, e);
+                }
+                return;
+            }
+        };
+
+        match self.handle_with_extension(&received, message, &context).await {
+            Ok(_) => {
+                if let Err(e) = received.ack().await {
+                    tracing::error!("Could not ack message {} - err: {}", context.message_id, e);
+                }
+            },
+            Err(e) => {
+                tracing::warn!("Message {} from {} failed, nacked for redelivery - err: {}", context.message_id, self.subscription, e);
+                if let Err(e) = received.nack().await {
+                    tracing::error!("Could not nack message {} - err: {}", context.message_id, e);
+                }
+            }
+        }
+    }
+
+    // Extend the ack deadline before it lapses, until the handler is done or the extension limit is reached
+    async fn handle_with_extension(
+        &self,
+        received: &ReceivedMessage,
+        message: H::Message,
+        context: &MessageContext,
+    ) -> Result<(), String> {
+        let started = Instant::now();
+        let max_extension = Duration::from_secs(self.max_ack_extension_secs);
+        let extend_every = Duration::from_secs((self.ack_deadline_secs as u64 * 2 / 3).max(1));
+        let mut extension = tokio::time::interval_at(tokio::time::Instant::now() + extend_every, extend_every);
+
+        let mut handling = pin!(self.handler.handle(message, context));
+        loop {
+            tokio::select! {
+                result = &mut handling => return result,
+                _ = extension.tick() => {
+                    if started.elapsed() >= max_extension {
+                        continue;
+                    }
+                    if let Err(e) = received.modify_ack_deadline(self.ack_deadline_secs).await {
+                        tracing::warn!("Could not extend ack deadline of message {} - err: {}", context.message_id, e);
+                    }
+                },
+            }
+        }
+    }
+}
\ No newline at end of file
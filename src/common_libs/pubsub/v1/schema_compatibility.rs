@@ -0,0 +1,346 @@
+use apache_avro::schema::{Name, ResolvedSchema};
+use apache_avro::Schema;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Outcome of resolving data written with one schema against another.
+///
+/// Errors are data which cannot be read. Warnings are compatible differences
+/// which still lose or invent data, e.g. fields dropped or filled by defaults.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SchemaCompatibility {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl SchemaCompatibility {
+    pub fn is_compatible(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+struct Checker<'s> {
+    writer_names: HashMap<Name, &'s Schema>,
+    reader_names: HashMap<Name, &'s Schema>,
+}
+
+// Logical types are read as their underlying type
+fn underlying(schema: &Schema) -> Schema {
+    match schema {
+        Schema::Date | Schema::TimeMillis => Schema::Int,
+        Schema::TimeMicros
+        | Schema::TimestampMillis
+        | Schema::TimestampMicros
+        | Schema::TimestampNanos
+        | Schema::LocalTimestampMillis
+        | Schema::LocalTimestampMicros
+        | Schema::LocalTimestampNanos => Schema::Long,
+        Schema::Uuid => Schema::String,
+        Schema::BigDecimal => Schema::Bytes,
+        Schema::Decimal(decimal) => underlying(&decimal.inner),
+        other => other.clone(),
+    }
+}
+
+fn type_name(schema: &Schema) -> String {
+    match schema {
+        Schema::Null => "null".to_string(),
+        Schema::Boolean => "boolean".to_string(),
+        Schema::Int => "int".to_string(),
+        Schema::Long => "long".to_string(),
+        Schema::Float => "float".to_string(),
+        Schema::Double => "double".to_string(),
+        Schema::Bytes => "bytes".to_string(),
+        Schema::String => "string".to_string(),
+        Schema::Array(_) => "array".to_string(),
+        Schema::Map(_) => "map".to_string(),
+        Schema::Union(union) => format!(
+            "union [{}]",
+            union.variants().iter().map(type_name).collect::<Vec<_>>().join(", ")
+        ),
+        Schema::Record(record) => format!("record {}", record.name),
+        Schema::Enum(enum_schema) => format!("enum {}", enum_schema.name),
+        Schema::Fixed(fixed) => format!("fixed {}", fixed.name),
+        Schema::Ref { name } => name.to_string(),
+        Schema::Date => "int (date)".to_string(),
+        Schema::TimeMillis => "int (time-millis)".to_string(),
+        Schema::TimeMicros => "long (time-micros)".to_string(),
+        Schema::TimestampMillis => "long (timestamp-millis)".to_string(),
+        Schema::TimestampMicros => "long (timestamp-micros)".to_string(),
+        Schema::TimestampNanos => "long (timestamp-nanos)".to_string(),
+        Schema::LocalTimestampMillis => "long (local-timestamp-millis)".to_string(),
+        Schema::LocalTimestampMicros => "long (local-timestamp-micros)".to_string(),
+        Schema::LocalTimestampNanos => "long (local-timestamp-nanos)".to_string(),
+        Schema::Uuid => "string (uuid)".to_string(),
+        other => format!("{:?}", other).to_lowercase(),
+    }
+}
+
+// Promotions allowed by the Avro specification, writer type to reader type
+fn is_promotion(writer: &Schema, reader: &Schema) -> bool {
+    matches!(
+        (writer, reader),
+        (Schema::Int, Schema::Long | Schema::Float | Schema::Double)
+            | (Schema::Long, Schema::Float | Schema::Double)
+            | (Schema::Float, Schema::Double)
+            | (Schema::String, Schema::Bytes)
+            | (Schema::Bytes, Schema::String)
+    )
+}
+
+impl<'s> Checker<'s> {
+    fn deref<'a>(&'a self, schema: &'a Schema, names: &'a HashMap<Name, &'s Schema>) -> &'a Schema {
+        match schema {
+            Schema::Ref { name } => names.get(name).copied().unwrap_or(schema),
+            other => other,
+        }
+    }
+
+    fn check(&self, writer: &Schema, reader: &Schema, path: &str, report: &mut SchemaCompatibility) {
+        let writer = self.deref(writer, &self.writer_names);
+        let reader = self.deref(reader, &self.reader_names);
+
+        match (writer, reader) {
+            // Every branch the writer may use must be readable
+            (Schema::Union(writer_union), _) => {
+                for variant in writer_union.variants() {
+                    self.check(variant, reader, path, report);
+                }
+            },
+            // The first matching branch of the reader is used
+            (_, Schema::Union(reader_union)) => {
+                let matching = reader_union.variants().iter()
+                    .map(|variant| {
+                        let mut branch_report = SchemaCompatibility::default();
+                        self.check(writer, variant, path, &mut branch_report);
+                        branch_report
+                    })
+                    .find(|branch_report| branch_report.is_compatible());
+                match matching {
+                    Some(branch_report) => report.warnings.extend(branch_report.warnings),
+                    None => report.errors.push(format!(
+                        "{}: {} matches no branch of {}",
+                        path, type_name(writer), type_name(reader)
+                    )),
+                }
+            },
+            (Schema::Record(writer_record), Schema::Record(reader_record)) => {
+                for reader_field in &reader_record.fields {
+                    let field_path = format!("{}.{}", path, reader_field.name);
+                    let writer_field = writer_record.lookup.get(&reader_field.name).or_else(|| {
+                        reader_field.aliases.iter().flatten()
+                            .find_map(|alias| writer_record.lookup.get(alias))
+                    });
+
+                    match writer_field {
+                        Some(&position) => self.check(&writer_record.fields[position].schema, &reader_field.schema, &field_path, report),
+                        None if reader_field.default.is_some() => report.warnings.push(format!(
+                            "{}: missing in writer, read as its default", field_path
+                        )),
+                        None => report.errors.push(format!(
+                            "{}: missing in writer and has no default", field_path
+                        )),
+                    }
+                }
+
+                for writer_field in &writer_record.fields {
+                    let is_read = reader_record.fields.iter().any(|reader_field| {
+                        reader_field.name == writer_field.name
+                            || reader_field.aliases.iter().flatten().any(|alias| alias == &writer_field.name)
+                    });
+                    if !is_read {
+                        report.warnings.push(format!("{}.{}: missing in reader, dropped", path, writer_field.name));
+                    }
+                }
+            },
+            (Schema::Enum(writer_enum), Schema::Enum(reader_enum)) => {
+                let unknown_symbols = writer_enum.symbols.iter()
+                    .filter(|symbol| !reader_enum.symbols.contains(symbol))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if unknown_symbols.is_empty() {
+                    return;
+                }
+                match &reader_enum.default {
+                    Some(default) => report.warnings.push(format!(
+                        "{}: symbols {} missing in reader, read as {}", path, unknown_symbols.join(", "), default
+                    )),
+                    None => report.errors.push(format!(
+                        "{}: symbols {} missing in reader, which has no default", path, unknown_symbols.join(", ")
+                    )),
+                }
+            },
+            (Schema::Array(writer_array), Schema::Array(reader_array)) => {
+                self.check(&writer_array.items, &reader_array.items, &format!("{}[]", path), report);
+            },
+            (Schema::Map(writer_map), Schema::Map(reader_map)) => {
+                self.check(&writer_map.types, &reader_map.types, &format!("{}{{}}", path), report);
+            },
+            (Schema::Fixed(writer_fixed), Schema::Fixed(reader_fixed)) => {
+                if writer_fixed.size != reader_fixed.size {
+                    report.errors.push(format!(
+                        "{}: fixed of {} bytes cannot be read as fixed of {} bytes",
+                        path, writer_fixed.size, reader_fixed.size
+                    ));
+                }
+            },
+            _ => {
+                let (writer_type, reader_type) = (underlying(writer), underlying(reader));
+                if writer_type == reader_type || is_promotion(&writer_type, &reader_type) {
+                    return;
+                }
+                report.errors.push(format!(
+                    "{}: {} cannot be read as {}",
+                    path, type_name(writer), type_name(reader)
+                ));
+            },
+        }
+    }
+}
+
+/// Check whether data written with `writer` can be read with `reader`.
+///
+/// Follows the Avro schema resolution rules, including type promotions and
+/// field aliases of the reader. Record and enum names are not compared.
+pub fn check_compatibility(writer: &Schema, reader: &Schema) -> Result<SchemaCompatibility, String> {
+    let writer_names = match ResolvedSchema::try_from(writer) {
+        Ok(resolved) => resolved.get_names().clone(),
+        Err(e) => return Err(format!("Failed to resolve writer schema: {}", e)),
+    };
+    let reader_names = match ResolvedSchema::try_from(reader) {
+        Ok(resolved) => resolved.get_names().clone(),
+        Err(e) => return Err(format!("Failed to resolve reader schema: {}", e)),
+    };
+
+    let checker = Checker { writer_names, reader_names };
+    let mut report = SchemaCompatibility::default();
+    checker.check(writer, reader, "$", &mut report);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value as JsonValue};
+
+    use crate::common_libs::pubsub::v1::avro_schema;
+    use crate::common_libs::pubsub::v1::models::test_stats::TestStats;
+
+    fn record(fields: JsonValue) -> Schema {
+        Schema::parse(&json!({"type": "record", "name": "Stat", "fields": fields})).unwrap()
+    }
+
+    fn check(writer_fields: JsonValue, reader_fields: JsonValue) -> SchemaCompatibility {
+        check_compatibility(&record(writer_fields), &record(reader_fields)).unwrap()
+    }
+
+    #[test]
+    fn identical_schemas_are_compatible() {
+        let schema = avro_schema::schema_of::<TestStats>().unwrap();
+        let report = check_compatibility(&schema, &schema).unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+    }
+
+    #[test]
+    fn promotions() {
+        for (writer, reader) in [("int", "long"), ("int", "double"), ("long", "float"), ("float", "double"), ("string", "bytes"), ("bytes", "string")] {
+            let report = check(json!([{"name": "value", "type": writer}]), json!([{"name": "value", "type": reader}]));
+            assert!(report.is_compatible(), "{} -> {}: {:?}", writer, reader, report.errors);
+        }
+        for (writer, reader) in [("long", "int"), ("double", "float"), ("string", "int"), ("boolean", "int")] {
+            let report = check(json!([{"name": "value", "type": writer}]), json!([{"name": "value", "type": reader}]));
+            assert_eq!(report.errors, vec![format!("$.value: {} cannot be read as {}", writer, reader)]);
+        }
+    }
+
+    #[test]
+    fn logical_types_are_read_as_their_underlying_type() {
+        let report = check(
+            json!([{"name": "at", "type": {"type": "long", "logicalType": "timestamp-millis"}}]),
+            json!([{"name": "at", "type": "long"}]),
+        );
+        assert!(report.is_compatible(), "{:?}", report.errors);
+
+        let report = check(
+            json!([{"name": "at", "type": "string"}]),
+            json!([{"name": "at", "type": {"type": "long", "logicalType": "timestamp-millis"}}]),
+        );
+        assert_eq!(report.errors, vec!["$.at: string cannot be read as long (timestamp-millis)".to_string()]);
+    }
+
+    #[test]
+    fn missing_fields_and_defaults() {
+        let report = check(
+            json!([{"name": "kept", "type": "string"}, {"name": "dropped", "type": "int"}]),
+            json!([
+                {"name": "kept", "type": "string"},
+                {"name": "defaulted", "type": ["null", "string"], "default": null},
+                {"name": "required", "type": "int"},
+            ]),
+        );
+        assert_eq!(report.errors, vec!["$.required: missing in writer and has no default".to_string()]);
+        assert_eq!(report.warnings, vec![
+            "$.defaulted: missing in writer, read as its default".to_string(),
+            "$.dropped: missing in reader, dropped".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn aliases_match_renamed_fields() {
+        let report = check(
+            json!([{"name": "old_name", "type": "string"}]),
+            json!([{"name": "new_name", "type": "string", "aliases": ["old_name"]}]),
+        );
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+    }
+
+    #[test]
+    fn union_branches() {
+        // A required value is read by the matching branch of a nullable reader
+        let report = check(json!([{"name": "count", "type": "int"}]), json!([{"name": "count", "type": ["null", "long"]}]));
+        assert!(report.is_compatible(), "{:?}", report.errors);
+
+        // Every branch of the writer must be readable
+        let report = check(json!([{"name": "count", "type": ["null", "int"]}]), json!([{"name": "count", "type": "int"}]));
+        assert_eq!(report.errors, vec!["$.count: null cannot be read as int".to_string()]);
+
+        let report = check(json!([{"name": "count", "type": ["null", "string"]}]), json!([{"name": "count", "type": ["null", "int"]}]));
+        assert_eq!(report.errors, vec!["$.count: string matches no branch of union [null, int]".to_string()]);
+    }
+
+    #[test]
+    fn enum_symbols() {
+        let writer = json!([{"name": "level", "type": {"type": "enum", "name": "Level", "symbols": ["LOW", "HIGH", "MAX"]}}]);
+
+        let report = check(writer.clone(), json!([{"name": "level", "type": {"type": "enum", "name": "Level", "symbols": ["LOW", "HIGH"]}}]));
+        assert_eq!(report.errors, vec!["$.level: symbols MAX missing in reader, which has no default".to_string()]);
+
+        let report = check(writer, json!([{"name": "level", "type": {"type": "enum", "name": "Level", "symbols": ["LOW", "HIGH"], "default": "HIGH"}}]));
+        assert!(report.is_compatible(), "{:?}", report.errors);
+        assert_eq!(report.warnings, vec!["$.level: symbols MAX missing in reader, read as HIGH".to_string()]);
+    }
+
+    #[test]
+    fn nested_records_arrays_and_maps() {
+        let point = |x_type: &str| json!({"type": "record", "name": "Point", "fields": [{"name": "x", "type": x_type}]});
+        let report = check(
+            json!([
+                {"name": "origin", "type": point("int")},
+                {"name": "corners", "type": {"type": "array", "items": "Point"}},
+                {"name": "tags", "type": {"type": "map", "values": "string"}},
+            ]),
+            json!([
+                {"name": "origin", "type": point("string")},
+                {"name": "corners", "type": {"type": "array", "items": "Point"}},
+                {"name": "tags", "type": {"type": "map", "values": "int"}},
+            ]),
+        );
+        assert_eq!(report.errors, vec![
+            "$.origin.x: int cannot be read as string".to_string(),
+            "$.corners[].x: int cannot be read as string".to_string(),
+            "$.tags{}: string cannot be read as int".to_string(),
+        ]);
+    }
+}
\ No newline at end of file
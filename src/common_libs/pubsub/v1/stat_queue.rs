@@ -0,0 +1,240 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+use super::pubsub_publisher::StatWithMetadata;
+
+/// Bounded multi-producer, single-consumer queue of stats.
+///
+/// Unlike a channel, producers can evict the oldest stat to make room.
+pub struct StatQueue {
+    items: Mutex<VecDeque<StatWithMetadata>>,
+    capacity: usize,
+    closed: AtomicBool,
+    item_added: Notify,
+    space_freed: Notify,
+}
+
+impl StatQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            items: Mutex::new(VecDeque::new()),
+            capacity: capacity.max(1),
+            closed: AtomicBool::new(false),
+            item_added: Notify::new(),
+            space_freed: Notify::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.lock().unwrap().len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Queue `item` if there is room, otherwise hand it back.
+    pub fn try_push(&self, item: StatWithMetadata) -> Result<(), StatWithMetadata> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(item);
+        }
+
+        let mut items = self.items.lock().unwrap();
+        if items.len() >= self.capacity {
+            return Err(item);
+        }
+        items.push_back(item);
+        drop(items);
+
+        self.item_added.notify_one();
+        Ok(())
+    }
+
+    /// Queue `item`, evicting and returning the oldest stat if the queue is full.
+    pub fn push_evicting(&self, item: StatWithMetadata) -> Result<Option<StatWithMetadata>, StatWithMetadata> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(item);
+        }
+
+        let mut items = self.items.lock().unwrap();
+        let evicted = match items.len() >= self.capacity {
+            true => items.pop_front(),
+            false => None,
+        };
+        items.push_back(item);
+        drop(items);
+
+        self.item_added.notify_one();
+        Ok(evicted)
+    }
+
+    /// Queue `item` once there is room, hands it back if the queue was closed.
+    pub async fn push(&self, mut item: StatWithMetadata) -> Result<(), StatWithMetadata> {
+        loop {
+            let space_freed = self.space_freed.notified();
+            item = match self.try_push(item) {
+                Ok(_) => return Ok(()),
+                Err(item) if self.closed.load(Ordering::Acquire) => return Err(item),
+                Err(item) => item,
+            };
+            space_freed.await;
+        }
+    }
+
+    /// Next stat, `None` once the queue is closed and empty.
+    pub async fn pop(&self) -> Option<StatWithMetadata> {
+        loop {
+            // A notification sent before this await is stored, so none is missed
+            if let Some(item) = self.try_pop() {
+                return Some(item);
+            }
+            if self.closed.load(Ordering::Acquire) {
+                return None;
+            }
+            self.item_added.notified().await;
+        }
+    }
+
+    pub fn try_pop(&self) -> Option<StatWithMetadata> {
+        let item = self.items.lock().unwrap().pop_front();
+        if item.is_some() {
+            self.space_freed.notify_one();
+        }
+        item
+    }
+
+    /// Reject further pushes, queued stats can still be popped.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.item_added.notify_one();
+        self.space_freed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::common_libs::pubsub::v1::models::{test_stats::TestStats, BoundStat};
+
+    const WAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+    fn stat(event_type: &str) -> StatWithMetadata {
+        StatWithMetadata {
+            binding: TestStats::BINDING,
+            stat: Box::new(TestStats { event_type: event_type.to_string(), ..Default::default() }),
+        }
+    }
+
+    fn event_type(item: &StatWithMetadata) -> String {
+        item.stat.attributes().remove("event_type").unwrap()
+    }
+
+    #[test]
+    fn try_push_respects_capacity() {
+        let queue = StatQueue::new(2);
+        assert!(queue.try_push(stat("a")).is_ok());
+        assert!(queue.try_push(stat("b")).is_ok());
+
+        let rejected = queue.try_push(stat("c")).err().unwrap();
+        assert_eq!(event_type(&rejected), "c");
+        assert_eq!(queue.len(), 2);
+
+        assert_eq!(queue.try_pop().map(|item| event_type(&item)).as_deref(), Some("a"));
+        assert!(queue.try_push(stat("c")).is_ok());
+    }
+
+    #[test]
+    fn push_evicting_returns_the_oldest() {
+        let queue = StatQueue::new(2);
+        assert!(queue.push_evicting(stat("a")).ok().unwrap().is_none());
+        assert!(queue.push_evicting(stat("b")).ok().unwrap().is_none());
+
+        let evicted = queue.push_evicting(stat("c")).ok().flatten().unwrap();
+        assert_eq!(event_type(&evicted), "a");
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.try_pop().map(|item| event_type(&item)).as_deref(), Some("b"));
+        assert_eq!(queue.try_pop().map(|item| event_type(&item)).as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn closed_queue_rejects_pushes() {
+        let queue = StatQueue::new(2);
+        queue.close();
+        assert!(queue.try_push(stat("a")).is_err());
+        assert!(queue.push_evicting(stat("a")).is_err());
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn push_waits_for_try_pop() {
+        let queue = Arc::new(StatQueue::new(1));
+        assert!(queue.try_push(stat("a")).is_ok());
+
+        let pusher = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.push(stat("b")).await.is_ok() }
+        });
+        tokio::task::yield_now().await;
+        assert!(!pusher.is_finished());
+
+        assert_eq!(queue.try_pop().map(|item| event_type(&item)).as_deref(), Some("a"));
+        assert!(tokio::time::timeout(WAKE_TIMEOUT, pusher).await.unwrap().unwrap());
+        assert_eq!(queue.try_pop().map(|item| event_type(&item)).as_deref(), Some("b"));
+    }
+
+    #[tokio::test]
+    async fn pop_waits_for_push() {
+        let queue = Arc::new(StatQueue::new(1));
+        let popper = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.pop().await.map(|item| event_type(&item)) }
+        });
+        tokio::task::yield_now().await;
+        assert!(!popper.is_finished());
+
+        assert!(queue.try_push(stat("a")).is_ok());
+        let popped = tokio::time::timeout(WAKE_TIMEOUT, popper).await.unwrap().unwrap();
+        assert_eq!(popped.as_deref(), Some("a"));
+    }
+
+    #[tokio::test]
+    async fn close_wakes_blocked_push_and_pop() {
+        let full_queue = Arc::new(StatQueue::new(1));
+        assert!(full_queue.try_push(stat("a")).is_ok());
+        let pusher = tokio::spawn({
+            let queue = full_queue.clone();
+            async move { queue.push(stat("b")).await.map_err(|item| event_type(&item)) }
+        });
+
+        let empty_queue = Arc::new(StatQueue::new(1));
+        let popper = tokio::spawn({
+            let queue = empty_queue.clone();
+            async move { queue.pop().await.is_none() }
+        });
+        tokio::task::yield_now().await;
+
+        full_queue.close();
+        empty_queue.close();
+        let pushed = tokio::time::timeout(WAKE_TIMEOUT, pusher).await.unwrap().unwrap();
+        assert_eq!(pushed, Err("b".to_string()));
+        assert!(tokio::time::timeout(WAKE_TIMEOUT, popper).await.unwrap().unwrap());
+    }
+
+    #[tokio::test]
+    async fn pop_drains_before_returning_none() {
+        let queue = StatQueue::new(2);
+        assert!(queue.try_push(stat("a")).is_ok());
+        assert!(queue.try_push(stat("b")).is_ok());
+        queue.close();
+
+        assert_eq!(queue.pop().await.map(|item| event_type(&item)).as_deref(), Some("a"));
+        assert_eq!(queue.pop().await.map(|item| event_type(&item)).as_deref(), Some("b"));
+        assert!(queue.pop().await.is_none());
+        assert!(queue.pop().await.is_none());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,96 @@
+use apache_avro::Schema;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use super::avro_parser::AvroParser;
+use super::avro_schema;
+use super::models::{BoundStat, StatBinding};
+use super::pubsub_client::PubSubClient;
+use super::schema_compatibility::SchemaCompatibility;
+
+/// Stat type checked against its topic and schema on startup.
+pub struct RegisteredStat {
+    pub name: &'static str,
+    pub binding: &'static StatBinding,
+    schema_json: fn() -> Result<serde_json::Value, String>,
+    check_struct: fn(&Schema) -> Result<SchemaCompatibility, String>,
+}
+
+impl RegisteredStat {
+    pub(super) fn of<T: BoundStat + DeserializeOwned>() -> Self {
+        Self {
+            name: std::any::type_name::<T>().rsplit("::").next().unwrap_or_default(),
+            binding: T::BINDING,
+            schema_json: avro_schema::schema_json_of::<T>,
+            check_struct: |schema| AvroParser.check_struct::<T>(schema),
+        }
+    }
+
+    /// Avro schema derived from the stat struct.
+    pub fn schema_json(&self) -> Result<serde_json::Value, String> {
+        (self.schema_json)()
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct StatCheck {
+    pub name: &'static str,
+    pub topic: &'static str,
+    pub schema_id: &'static str,
+    // Topic or schema missing, or the struct cannot be encoded with the schema
+    pub errors: Vec<String>,
+    // Struct and schema differ in a compatible way
+    pub warnings: Vec<String>,
+}
+
+pub use super::models::registered_stats;
+
+/// Check that every registered topic and schema exists and matches its stat type.
+///
+/// Schemas are fetched from Pub/Sub rather than the caches, so that a check
+/// sees the latest revision. Errors fail startup in prod only.
+pub async fn check_registered_stats(
+    pubsub_client: &PubSubClient,
+    project_id: &str,
+) -> Vec<StatCheck> {
+    let mut checks = Vec::new();
+
+    // A topic validates against a single schema
+    let mut topic_schemas: HashMap<&str, &str> = HashMap::new();
+    for registered in registered_stats() {
+        let binding = registered.binding;
+        let mut check = StatCheck {
+            name: registered.name,
+            topic: binding.topic,
+            schema_id: binding.schema_id,
+            ..Default::default()
+        };
+
+        match topic_schemas.insert(binding.topic, binding.schema_id) {
+            Some(schema_id) if schema_id != binding.schema_id => {
+                check.errors.push(format!("Topic {} is bound to schemas {} and {}", binding.topic, schema_id, binding.schema_id));
+            },
+            _ => {},
+        }
+
+        if let Err(e) = pubsub_client.check_topic(binding.topic, binding.schema_id).await {
+            check.errors.push(e);
+        }
+
+        match pubsub_client.fetch_schema(project_id, binding.schema_id).await {
+            Ok(schema) => match (registered.check_struct)(&schema) {
+                Ok(compatibility) => {
+                    check.errors.extend(compatibility.errors);
+                    check.warnings.extend(compatibility.warnings);
+                },
+                Err(e) => check.errors.push(e),
+            },
+            Err(e) => check.errors.push(e),
+        }
+
+        checks.push(check);
+    }
+
+    checks
+}
\ No newline at end of file
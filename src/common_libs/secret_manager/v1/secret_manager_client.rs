@@ -3,8 +3,12 @@ use base64::engine::general_purpose::STANDARD;
 use google_cloud_secretmanager_v1::client::SecretManagerService;
 use serde::de::DeserializeOwned;
 
+use crate::common_libs::cache_service::v1::{cache_key::CacheNamespace, tiered_cache::CacheOptions};
 use crate::state::APP_STATE;
 
+const SECRET_LOAD_LOCK_MS: u64 = 2000;
+const SECRET_CACHE_NAMESPACE: CacheNamespace = CacheNamespace::new("secret_manager", 1);
+
 pub struct SecretManagerClient {
     client: SecretManagerService,
     project_id: String,
@@ -17,29 +21,12 @@ impl SecretManagerClient {
         Self { client, project_id }
     }
 
-    pub async fn get_secret_manager_data(
+    /// Fetch a secret directly from Secret Manager, bypassing the caches.
+    pub async fn fetch_secret_data(
         &self,
         key: &str,
         version: &str,
-        ttl: u64,
     ) -> Result<String, String> {
-        let app_state = APP_STATE.get().unwrap();
-
-        // Create cache key
-        let cache_key = format!("secret_manager:{}:{}", version, key);
-
-        // Try instance cache
-        if let Some(secret_data) = app_state.instance_cache.get::<String>(&cache_key) {
-            return Ok(secret_data.to_string());
-        }
-
-        // Try Redis cache
-        if let Ok(Some(secret_data)) = app_state.redis_client.get::<String>(&cache_key).await {
-            // Cache in instance cache
-            app_state.instance_cache.set(&cache_key, secret_data.clone(), ttl);
-            return Ok(secret_data);
-        }
-
         let name = format!("projects/{}/secrets/{}/versions/{}", self.project_id, key, version);
         let payload = match self.client
             .access_secret_version()
@@ -63,16 +50,40 @@ impl SecretManagerClient {
             Err(e) => return Err(format!("Failed to decode secret data: {}", e)),
         };
 
-        // Cache in Redis
-        match app_state.redis_client.set(cache_key.clone(), data.clone(), Some(86400)).await {
-            Ok(_) => {},
-            Err(e) => tracing::error!("Failed to set secret in Redis - err: {}", e),
-        }
+        Ok(data)
+    }
+
+    pub async fn get_secret_manager_data(
+        &self,
+        key: &str,
+        version: &str,
+        ttl: u64,
+    ) -> Result<String, String> {
+        let app_state = APP_STATE.get().unwrap();
 
-        // Cache in instance cache
-        app_state.instance_cache.set(&cache_key, data.clone(), ttl);
+        // Create cache key
+        let cache_key = app_state.cache_keys.key(&SECRET_CACHE_NAMESPACE, &[version, key]).await;
 
-        Ok(data)
+        // Try instance cache, then Redis, then Secret Manager with a single fetch per key
+        let options = CacheOptions::new(ttl, ttl).redis_lock(SECRET_LOAD_LOCK_MS);
+        let (key, version) = (key.to_string(), version.to_string());
+        app_state.tiered_cache.get_or_load(&cache_key, &options, move || async move {
+            let app_state = APP_STATE.get().unwrap();
+            app_state.secret_manager_client.fetch_secret_data(&key, &version).await
+        }).await
+    }
+
+    /// Drop a rotated secret from Redis and from the instance cache of every instance.
+    #[allow(dead_code)]
+    pub async fn invalidate_secret(
+        &self,
+        key: &str,
+        version: &str,
+    ) -> Result<(), String> {
+        let app_state = APP_STATE.get().unwrap();
+        let cache_key = app_state.cache_keys.key(&SECRET_CACHE_NAMESPACE, &[version, key]).await;
+
+        app_state.tiered_cache.delete(&cache_key).await
     }
 
     #[allow(dead_code)]
@@ -1,3 +1,4 @@
+pub mod rate_limiter;
 pub mod request_parser;
 pub mod security_headers;
 pub mod structured_logging;
\ No newline at end of file
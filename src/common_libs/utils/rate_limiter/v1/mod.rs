@@ -0,0 +1,387 @@
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header};
+use axum::Json;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use deadpool_redis::redis::Script;
+use once_cell::sync::Lazy;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::common_libs::cache_service::v1::redis_client::RedisClient;
+use crate::common_libs::utils::security_headers::v1::add_headers;
+use crate::config::{RateLimitAlgorithm, RateLimitConfig, RateLimitKey, RateLimitRule};
+use crate::state::AppState;
+
+const RATE_LIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+const RATE_LIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+const RATE_LIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+// All scripts return {allowed, remaining, reset_ms, retry_after_ms}
+
+// KEYS[1] = window counter, ARGV = {limit, window_ms}
+static FIXED_WINDOW_SCRIPT: Lazy<Script> = Lazy::new(|| Script::new(r"
+    local limit = tonumber(ARGV[1])
+    local window = tonumber(ARGV[2])
+    local count = redis.call('INCR', KEYS[1])
+    local ttl = redis.call('PTTL', KEYS[1])
+    if ttl < 0 then
+        redis.call('PEXPIRE', KEYS[1], window)
+        ttl = window
+    end
+    if count > limit then
+        return {0, 0, ttl, ttl}
+    end
+    return {1, limit - count, ttl, 0}
+"));
+
+// KEYS = {current window counter, previous window counter}, ARGV = {limit, window_ms, elapsed_ms}
+static SLIDING_WINDOW_SCRIPT: Lazy<Script> = Lazy::new(|| Script::new(r"
+    local limit = tonumber(ARGV[1])
+    local window = tonumber(ARGV[2])
+    local elapsed = tonumber(ARGV[3])
+    local current = tonumber(redis.call('GET', KEYS[1]) or '0')
+    local previous = tonumber(redis.call('GET', KEYS[2]) or '0')
+    local weighted = previous * (window - elapsed) / window + current
+    if weighted + 1 > limit then
+        local retry = window - elapsed
+        if current + 1 <= limit and previous > 0 then
+            -- wait until enough of the previous window has slid out
+            local slide_to = window * (1 - (limit - 1 - current) / previous)
+            retry = math.max(1, math.ceil(slide_to - elapsed))
+        end
+        return {0, 0, window - elapsed, retry}
+    end
+    current = redis.call('INCR', KEYS[1])
+    if current == 1 then
+        redis.call('PEXPIRE', KEYS[1], window * 2)
+    end
+    return {1, math.floor(limit - weighted - 1), window - elapsed, 0}
+"));
+
+// KEYS[1] = bucket hash, ARGV = {capacity, window_ms, now_ms}
+static TOKEN_BUCKET_SCRIPT: Lazy<Script> = Lazy::new(|| Script::new(r"
+    local capacity = tonumber(ARGV[1])
+    local window = tonumber(ARGV[2])
+    local now = tonumber(ARGV[3])
+    local rate = capacity / window
+    local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+    local tokens = tonumber(bucket[1]) or capacity
+    local ts = tonumber(bucket[2]) or now
+    tokens = math.min(capacity, tokens + math.max(0, now - ts) * rate)
+    local allowed = 0
+    local retry = 0
+    if tokens >= 1 then
+        tokens = tokens - 1
+        allowed = 1
+    else
+        retry = math.ceil((1 - tokens) / rate)
+    end
+    redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', now)
+    redis.call('PEXPIRE', KEYS[1], window)
+    return {allowed, math.floor(tokens), math.ceil((capacity - tokens) / rate), retry}
+"));
+
+#[derive(Debug)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    pub reset_ms: u64,
+    pub retry_after_ms: u64,
+}
+
+impl RateLimitDecision {
+    fn from_script_result(limit: u64, result: Vec<i64>) -> Result<Self, String> {
+        match result.as_slice() {
+            [allowed, remaining, reset_ms, retry_after_ms] => Ok(Self {
+                allowed: *allowed == 1,
+                limit,
+                remaining: (*remaining).max(0) as u64,
+                reset_ms: (*reset_ms).max(0) as u64,
+                retry_after_ms: (*retry_after_ms).max(0) as u64,
+            }),
+            other => Err(format!("Unexpected rate limit script result: {:?}", other)),
+        }
+    }
+
+    fn add_headers(&self, headers: &mut HeaderMap) {
+        headers.insert(RATE_LIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(RATE_LIMIT_REMAINING, HeaderValue::from(self.remaining));
+        headers.insert(RATE_LIMIT_RESET, HeaderValue::from(self.reset_ms.div_ceil(1000)));
+        if !self.allowed {
+            headers.insert(header::RETRY_AFTER, HeaderValue::from(self.retry_after_ms.div_ceil(1000).max(1)));
+        }
+    }
+}
+
+pub struct RateLimiter;
+
+impl RateLimiter {
+    /// Find the first rule whose path matches the request path on a segment boundary.
+    pub fn match_rule<'a>(config: &'a RateLimitConfig, path: &str) -> Option<&'a RateLimitRule> {
+        config.rules.iter().find(|rule| {
+            let rule_path = rule.path.trim_end_matches('/');
+            path == rule_path
+                || path.strip_prefix(rule_path).is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+
+    /// Extract the client IP from `x-forwarded-for`, skipping entries appended by trusted proxies.
+    pub fn client_ip(headers: &HeaderMap, trusted_proxies: usize) -> Option<String> {
+        let forwarded_for = headers.get("x-forwarded-for").and_then(|hv| hv.to_str().ok())?;
+        let hops = forwarded_for.split(',')
+            .map(|hop| hop.trim())
+            .filter(|hop| !hop.is_empty())
+            .collect::<Vec<_>>();
+        let index = hops.len().checked_sub(trusted_proxies + 1)?;
+        Some(hops[index].to_string())
+    }
+
+    fn client_key(config: &RateLimitConfig, rule: &RateLimitRule, headers: &HeaderMap) -> String {
+        match rule.key {
+            RateLimitKey::Ip => {
+                let ip = Self::client_ip(headers, config.trusted_proxies).unwrap_or_else(|| "unknown".to_string());
+                format!("ip:{}", ip)
+            },
+            RateLimitKey::ApiKey => {
+                // Requests without an API key are limited per client IP instead
+                match headers.get(config.api_key_header.as_str()).and_then(|hv| hv.to_str().ok()) {
+                    Some(api_key) => format!("api_key:{}", api_key),
+                    None => {
+                        let ip = Self::client_ip(headers, config.trusted_proxies).unwrap_or_else(|| "unknown".to_string());
+                        format!("ip:{}", ip)
+                    }
+                }
+            },
+            RateLimitKey::Route => "route".to_string(),
+        }
+    }
+
+    pub async fn check(
+        redis_client: &RedisClient,
+        rule: &RateLimitRule,
+        client_key: &str,
+    ) -> Result<RateLimitDecision, String> {
+        let window_ms = rule.window_secs.max(1) * 1000;
+        // Hash tag keeps every key of a client in one cluster slot
+        let base_key = format!("rate_limit:{{{}:{}}}", rule.path, client_key);
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+
+        let result = match rule.algorithm {
+            RateLimitAlgorithm::FixedWindow => {
+                redis_client.eval_script::<u64, Vec<i64>>(
+                    &FIXED_WINDOW_SCRIPT,
+                    &[base_key],
+                    &[rule.limit, window_ms],
+                ).await?
+            },
+            RateLimitAlgorithm::SlidingWindow => {
+                let window_index = now_ms / window_ms;
+                redis_client.eval_script::<u64, Vec<i64>>(
+                    &SLIDING_WINDOW_SCRIPT,
+                    &[
+                        format!("{}:{}", base_key, window_index),
+                        format!("{}:{}", base_key, window_index.saturating_sub(1)),
+                    ],
+                    &[rule.limit, window_ms, now_ms % window_ms],
+                ).await?
+            },
+            RateLimitAlgorithm::TokenBucket => {
+                redis_client.eval_script::<u64, Vec<i64>>(
+                    &TOKEN_BUCKET_SCRIPT,
+                    &[base_key],
+                    &[rule.limit, window_ms, now_ms],
+                ).await?
+            },
+        };
+
+        RateLimitDecision::from_script_result(rule.limit, result)
+    }
+}
+
+/// Axum middleware enforcing the rate limit rules configured in `AppConfig::rate_limit`.
+///
+/// Requests are allowed through when Redis is unavailable.
+pub async fn rate_limit(
+    State(app_state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let config = &app_state.config.rate_limit;
+    if !config.enabled {
+        return next.run(request).await;
+    }
+
+    let rule = match RateLimiter::match_rule(config, request.uri().path()) {
+        Some(rule) => rule,
+        None => return next.run(request).await,
+    };
+
+    let client_key = RateLimiter::client_key(config, rule, request.headers());
+    let decision = match RateLimiter::check(&app_state.redis_client, rule, &client_key).await {
+        Ok(decision) => decision,
+        Err(e) => {
+            tracing::error!("Rate limit check failed, allowing request - err: {}", e);
+            return next.run(request).await;
+        }
+    };
+
+    if !decision.allowed {
+        tracing::warn!("Rate limit exceeded - path: {}, client: {}", rule.path, client_key);
+        let mut headers = add_headers();
+        decision.add_headers(&mut headers);
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            headers,
+            Json(json!({
+                "success": false,
+                "error": "Too many requests"
+            }))
+        ).into_response();
+    }
+
+    let mut response = next.run(request).await;
+    decision.add_headers(response.headers_mut());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common_libs::cache_service::v1::redis_client::tests::test_client;
+
+    fn forwarded_for(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    fn config(trusted_proxies: usize) -> RateLimitConfig {
+        RateLimitConfig {
+            enabled: true,
+            api_key_header: "x-api-key".to_string(),
+            trusted_proxies,
+            rules: vec![
+                rule("/app/stats", RateLimitAlgorithm::FixedWindow, RateLimitKey::ApiKey, 10),
+                rule("/app/", RateLimitAlgorithm::FixedWindow, RateLimitKey::Ip, 10),
+            ],
+        }
+    }
+
+    fn rule(path: &str, algorithm: RateLimitAlgorithm, key: RateLimitKey, limit: u64) -> RateLimitRule {
+        RateLimitRule {
+            path: path.to_string(),
+            algorithm,
+            key,
+            limit,
+            window_secs: 60,
+        }
+    }
+
+    #[test]
+    fn client_ip_without_proxies_is_the_last_hop() {
+        assert_eq!(RateLimiter::client_ip(&forwarded_for("203.0.113.7"), 0).as_deref(), Some("203.0.113.7"));
+        // Entries before the last one are set by the client and may be spoofed
+        assert_eq!(RateLimiter::client_ip(&forwarded_for("1.2.3.4, 203.0.113.7"), 0).as_deref(), Some("203.0.113.7"));
+    }
+
+    #[test]
+    fn client_ip_skips_trusted_proxies() {
+        let headers = forwarded_for("1.2.3.4, 203.0.113.7, 10.0.0.1");
+        assert_eq!(RateLimiter::client_ip(&headers, 1).as_deref(), Some("203.0.113.7"));
+        assert_eq!(RateLimiter::client_ip(&headers, 2).as_deref(), Some("1.2.3.4"));
+
+        let headers = forwarded_for("203.0.113.7, 10.0.0.1, 10.0.0.2");
+        assert_eq!(RateLimiter::client_ip(&headers, 2).as_deref(), Some("203.0.113.7"));
+    }
+
+    #[test]
+    fn client_ip_with_fewer_hops_than_proxies_is_unknown() {
+        // Rather than the address of a proxy, which would limit every client at once
+        assert_eq!(RateLimiter::client_ip(&forwarded_for("10.0.0.1"), 1), None);
+        assert_eq!(RateLimiter::client_ip(&forwarded_for("203.0.113.7, 10.0.0.1"), 2), None);
+        assert_eq!(RateLimiter::client_ip(&HeaderMap::new(), 0), None);
+    }
+
+    #[test]
+    fn client_ip_ignores_blank_entries() {
+        let headers = forwarded_for(" 203.0.113.7 ,, 10.0.0.1 , ");
+        assert_eq!(RateLimiter::client_ip(&headers, 1).as_deref(), Some("203.0.113.7"));
+        assert_eq!(RateLimiter::client_ip(&forwarded_for(" , "), 0), None);
+    }
+
+    #[test]
+    fn client_key_falls_back_to_ip_without_api_key() {
+        let config = config(1);
+        let mut headers = forwarded_for("203.0.113.7, 10.0.0.1");
+        assert_eq!(RateLimiter::client_key(&config, &config.rules[0], &headers), "ip:203.0.113.7");
+        assert_eq!(RateLimiter::client_key(&config, &config.rules[1], &HeaderMap::new()), "ip:unknown");
+
+        headers.insert("x-api-key", HeaderValue::from_static("key-1"));
+        assert_eq!(RateLimiter::client_key(&config, &config.rules[0], &headers), "api_key:key-1");
+    }
+
+    #[test]
+    fn match_rule_on_segment_boundary() {
+        let config = config(0);
+        assert_eq!(RateLimiter::match_rule(&config, "/app/stats").map(|rule| rule.path.as_str()), Some("/app/stats"));
+        assert_eq!(RateLimiter::match_rule(&config, "/app/stats/1").map(|rule| rule.path.as_str()), Some("/app/stats"));
+        assert_eq!(RateLimiter::match_rule(&config, "/app/statsx").map(|rule| rule.path.as_str()), Some("/app/"));
+        assert_eq!(RateLimiter::match_rule(&config, "/app").map(|rule| rule.path.as_str()), Some("/app/"));
+        assert!(RateLimiter::match_rule(&config, "/application").is_none());
+    }
+
+    #[test]
+    fn decision_from_script_result() {
+        let decision = RateLimitDecision::from_script_result(10, vec![0, -1, 1500, 1001]).unwrap();
+        assert!(!decision.allowed);
+        assert_eq!(decision.remaining, 0);
+
+        let mut headers = HeaderMap::new();
+        decision.add_headers(&mut headers);
+        assert_eq!(headers[RATE_LIMIT_RESET], "2");
+        assert_eq!(headers[header::RETRY_AFTER], "2");
+
+        assert!(RateLimitDecision::from_script_result(10, vec![1, 2]).is_err());
+    }
+
+    async fn assert_limits(algorithm: RateLimitAlgorithm) {
+        let client = test_client();
+        let rule = rule(&format!("/test/{:08x}", rand::random::<u32>()), algorithm, RateLimitKey::Route, 2);
+
+        for remaining in [1, 0] {
+            let decision = RateLimiter::check(&client, &rule, "route").await.unwrap();
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+        }
+        let decision = RateLimiter::check(&client, &rule, "route").await.unwrap();
+        assert!(!decision.allowed);
+        assert!(decision.retry_after_ms > 0 && decision.retry_after_ms <= 60_000, "{:?}", decision);
+
+        // Other clients have their own limit
+        assert!(RateLimiter::check(&client, &rule, "other").await.unwrap().allowed);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local Redis, set REDIS_URL and run with --ignored"]
+    async fn fixed_window_script() {
+        assert_limits(RateLimitAlgorithm::FixedWindow).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local Redis, set REDIS_URL and run with --ignored"]
+    async fn sliding_window_script() {
+        assert_limits(RateLimitAlgorithm::SlidingWindow).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a local Redis, set REDIS_URL and run with --ignored"]
+    async fn token_bucket_script() {
+        assert_limits(RateLimitAlgorithm::TokenBucket).await;
+    }
+}
\ No newline at end of file
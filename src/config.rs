@@ -2,11 +2,208 @@ use config::{Config, ConfigError, Environment, File};
 use serde::{Deserialize, Serialize};
 use std::env;
 
+// Settings of the Pub/Sub client publisher kept per topic
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PubSubPublishConfig {
+    // Concurrent publish requests, each worker has its own gRPC channel
+    pub workers: usize,
+    pub flush_interval_ms: u64,
+    // Messages sent per publish request
+    pub bundle_size: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PubSubRetryConfig {
+    // Including the first attempt
+    pub max_attempts: u32,
+    // Doubled after each attempt, with jitter
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SpillTarget {
+    // Local files are lost with the instance, only for development
+    Disk,
+    Gcs,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PubSubSpillConfig {
+    pub target: SpillTarget,
+    pub directory: String,
+    pub bucket: String,
+    // Object name prefix in the bucket
+    pub prefix: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    // Wait up to `block_timeout_ms` for room, then reject
+    Block,
+    DropNewest,
+    DropOldest,
+    // Fail right away so that handlers answer 503
+    Reject,
+}
+
+// OIDC token attached by Pub/Sub to push requests, verified if `audience` is set
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PubSubPushAuthConfig {
+    pub audience: String,
+    // Service account email the subscription pushes as
+    pub service_account: String,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct PubSubConfig {
+    // Per-topic batch limits, capped at Pub/Sub's 1000 messages and 10MB per request
     pub max_messages: usize,
+    // Approximate Avro encoded size
     pub max_bytes: usize,
+    // Seconds after its first stat that a batch is published
     pub max_latency: u64,
+    // Stats queued for batching, `overflow_policy` applies once it is full
+    pub channel_capacity: usize,
+    pub overflow_policy: OverflowPolicy,
+    pub block_timeout_ms: u64,
+    // Expected `token` query parameter of push requests, empty to accept any
+    pub push_verification_token: String,
+    // Secret Manager secret holding the push token, replaces `push_verification_token` if set.
+    // Prod requires a push token or `push_auth`
+    pub push_verification_token_secret: String,
+    pub push_verification_token_secret_version: String,
+    pub push_auth: PubSubPushAuthConfig,
+    pub publisher: PubSubPublishConfig,
+    pub retry: PubSubRetryConfig,
+    // Where batches are stored for replay once all publish attempts failed
+    pub spill: PubSubSpillConfig,
+    // Time from SIGTERM to finish in-flight requests and publish buffered stats, anything
+    // left is spilled then. Cloud Run kills the instance 10s after SIGTERM, keep room to spill
+    pub shutdown_timeout_secs: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionPolicy {
+    Lru,
+    // LRU with admission of new keys only if used more often than the LRU victim
+    TinyLfu,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct InstanceCacheConfig {
+    pub max_entries: usize,
+    // Approximate, based on the size hint given for each value. A single value
+    // must fit into one shard, i.e. at most `max_bytes / shards`
+    pub max_bytes: usize,
+    pub eviction: EvictionPolicy,
+    // Independently locked partitions, the limits are split evenly between them
+    pub shards: usize,
+    pub sweep_interval_secs: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionAlgorithm {
+    None,
+    Gzip,
+    Zstd,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RedisCompressionConfig {
+    pub algorithm: CompressionAlgorithm,
+    pub level: i32,
+    pub threshold_bytes: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RedisPoolConfig {
+    pub max_size: usize,
+    pub wait_timeout_ms: u64,
+    pub create_timeout_ms: u64,
+    pub recycle_timeout_ms: u64,
+    pub command_timeout_ms: u64,
+    pub health_check_interval_secs: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CircuitBreakerConfig {
+    pub enabled: bool,
+    // Consecutive failed calls after which calls are rejected
+    pub failure_threshold: u32,
+    // How long calls are rejected before a trial call is let through
+    pub open_duration_ms: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RedisMode {
+    Standalone,
+    Sentinel,
+    // Multi-key commands (transactions, scripts, mset) need keys sharing a hash tag,
+    // SCAN only covers the node it is routed to. Replicated and partitioned values
+    // are written under `{key}` hash tags, so they are not shared with standalone
+    // deployments of the python services
+    Cluster,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RedisConfig {
+    pub mode: RedisMode,
+    // "host:port" of the server, the sentinels or the cluster seed nodes
+    pub nodes: Vec<String>,
+    // Sentinel master name
+    pub master_name: String,
+    // Ignored in cluster mode
+    pub db: i64,
+    pub tls: bool,
+    // Skip certificate verification
+    pub tls_insecure: bool,
+    // ACL username, empty for the default user
+    pub username: String,
+    // Secret Manager secret holding the AUTH password, empty for no password
+    pub password_secret: String,
+    pub password_secret_version: String,
+    pub pool: RedisPoolConfig,
+    pub compression: RedisCompressionConfig,
+    pub circuit_breaker: CircuitBreakerConfig,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitAlgorithm {
+    FixedWindow,
+    SlidingWindow,
+    TokenBucket,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    Ip,
+    ApiKey,
+    Route,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RateLimitRule {
+    pub path: String,
+    pub algorithm: RateLimitAlgorithm,
+    pub key: RateLimitKey,
+    pub limit: u64,
+    pub window_secs: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub api_key_header: String,
+    pub trusted_proxies: usize,
+    pub rules: Vec<RateLimitRule>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -16,9 +213,10 @@ pub struct AppConfig {
     pub google_cloud_project: String,
     pub gae_service: String,
     pub port: u16,
-    pub redishost: String,
-    pub redisport: u16,
+    pub redis: RedisConfig,
+    pub instance_cache: InstanceCacheConfig,
     pub pubsub: PubSubConfig,
+    pub rate_limit: RateLimitConfig,
 }
 
 impl AppConfig {
@@ -35,15 +233,35 @@ impl AppConfig {
             .add_source(File::with_name("config/local").required(false))
             // Add environment variables
             .add_source(Environment::default())
+            // Spill bucket of the deployment, provisioned per project
+            .set_override_option("pubsub.spill.bucket", env::var("PUBSUB_SPILL_BUCKET").ok())?
             .build()?;
 
         config.try_deserialize::<AppConfig>()
     }
 
-    #[allow(dead_code)]
     pub fn is_live_env(&self) -> bool {
         self.env == "prod"
     }
+
+    /// Reject settings which are unsafe in the live environment.
+    pub fn validate(&self) -> Result<(), String> {
+        let has_push_token = !self.pubsub.push_verification_token.is_empty()
+            || !self.pubsub.push_verification_token_secret.is_empty();
+        if self.is_live_env() && !has_push_token && !self.pubsub.push_auth.is_enabled() {
+            return Err("pubsub.push_verification_token, push_verification_token_secret or push_auth must be set in prod".to_string());
+        }
+        if self.pubsub.push_auth.is_enabled() && self.pubsub.push_auth.service_account.is_empty() {
+            return Err("pubsub.push_auth.service_account must be set with push_auth.audience".to_string());
+        }
+        if self.is_live_env() && self.pubsub.spill.target == SpillTarget::Disk {
+            return Err("pubsub.spill.target must be gcs in prod, spilled batches on disk are lost with the instance".to_string());
+        }
+        if self.pubsub.spill.target == SpillTarget::Gcs && self.pubsub.spill.bucket.is_empty() {
+            return Err("pubsub.spill.bucket must be set for the gcs spill target".to_string());
+        }
+        Ok(())
+    }
 }
 
 // Default implementation
@@ -55,12 +273,87 @@ impl Default for AppConfig {
             google_cloud_project: "getcloudy-469014".into(),
             gae_service: "rust-gcp".into(),
             port: 8080,
-            redishost: "10.207.177.140".into(),
-            redisport: 6379,
+            redis: RedisConfig {
+                mode: RedisMode::Standalone,
+                nodes: vec!["10.207.177.140:6379".into()],
+                master_name: "mymaster".into(),
+                db: 0,
+                tls: false,
+                tls_insecure: false,
+                username: "".into(),
+                password_secret: "".into(),
+                password_secret_version: "latest".into(),
+                pool: RedisPoolConfig {
+                    max_size: 32,
+                    wait_timeout_ms: 1000,
+                    create_timeout_ms: 1000,
+                    recycle_timeout_ms: 500,
+                    command_timeout_ms: 2000,
+                    health_check_interval_secs: 30,
+                },
+                compression: RedisCompressionConfig {
+                    algorithm: CompressionAlgorithm::Zstd,
+                    level: 3,
+                    threshold_bytes: 1024,
+                },
+                circuit_breaker: CircuitBreakerConfig {
+                    enabled: true,
+                    failure_threshold: 5,
+                    open_duration_ms: 5000,
+                },
+            },
+            instance_cache: InstanceCacheConfig {
+                max_entries: 10000,
+                max_bytes: 67108864,
+                eviction: EvictionPolicy::TinyLfu,
+                shards: 16,
+                sweep_interval_secs: 60,
+            },
             pubsub: PubSubConfig {
                 max_messages: 10,
                 max_bytes: 1024,
                 max_latency: 5,
+                channel_capacity: 1000,
+                overflow_policy: OverflowPolicy::Block,
+                block_timeout_ms: 100,
+                push_verification_token: "".into(),
+                push_verification_token_secret: "".into(),
+                push_verification_token_secret_version: "latest".into(),
+                push_auth: PubSubPushAuthConfig {
+                    audience: "".into(),
+                    service_account: "".into(),
+                },
+                publisher: PubSubPublishConfig {
+                    workers: 3,
+                    flush_interval_ms: 100,
+                    bundle_size: 100,
+                },
+                retry: PubSubRetryConfig {
+                    max_attempts: 5,
+                    initial_backoff_ms: 200,
+                    max_backoff_ms: 10000,
+                },
+                spill: PubSubSpillConfig {
+                    target: SpillTarget::Disk,
+                    directory: "/tmp/pubsub_spill".into(),
+                    bucket: "".into(),
+                    prefix: "pubsub_spill".into(),
+                },
+                shutdown_timeout_secs: 8,
+            },
+            rate_limit: RateLimitConfig {
+                enabled: true,
+                api_key_header: "x-api-key".into(),
+                trusted_proxies: 0,
+                rules: vec![
+                    RateLimitRule {
+                        path: "/app/rust_test/stats".into(),
+                        algorithm: RateLimitAlgorithm::SlidingWindow,
+                        key: RateLimitKey::Ip,
+                        limit: 100,
+                        window_secs: 60,
+                    },
+                ],
             },
         }
     }
@@ -3,13 +3,22 @@ mod config;
 mod routes;
 mod state;
 
+use std::collections::HashMap;
 use std::error::Error;
+use std::future::IntoFuture;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
+use tokio::signal;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
 use tracing_stackdriver::{layer, CloudTraceConfiguration};
 use tracing_subscriber::{EnvFilter, prelude::*};
 
-use common_libs::pubsub::v1::pubsub_publisher::PubSubPublisher;
+use common_libs::cache_service::v1::{instance_cache::InstanceCache, redis_client::RedisClient};
+use common_libs::pubsub::v1::{pubsub_client::PubSubClient, pubsub_publisher::PubSubPublisher, stat_registry};
+
+const CHECK_STAT_SCHEMAS_COMMAND: &str = "check-stat-schemas";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -25,21 +34,156 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .with(stackdriver_layer)
         .init();
 
+    // `check-stat-schemas` only reports drift between stat structs and their schemas,
+    // it needs Pub/Sub but none of the other services of the app state
+    if std::env::args().nth(1).as_deref() == Some(CHECK_STAT_SCHEMAS_COMMAND) {
+        let pubsub_client = PubSubClient::new().await;
+        let diverged = check_stat_schemas(&pubsub_client, &config.google_cloud_project).await;
+        std::process::exit(if diverged { 1 } else { 0 });
+    }
+
     tracing::info!("Starting Application");
 
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", config.port)).await.unwrap();
+    if let Err(e) = config.validate() {
+        tracing::error!("Invalid configuration - err: {}", e);
+        return Err(e.into());
+    }
+
+    let port = config.port;
 
     let app_state = Arc::new(state::AppState::new(config).await);
 
     // Initialize global state
     let _ = state::APP_STATE.set(app_state.clone());
 
+    // Check that stat types match their topics and schemas, stats which cannot be published
+    // would only be spilled, so prod refuses to start
+    let mut failed_checks = 0;
+    for check in stat_registry::check_registered_stats(&app_state.pubsub_client, &app_state.config.google_cloud_project).await {
+        for error in &check.errors {
+            tracing::error!("Stat binding check failed for {} - err: {}", check.name, error);
+        }
+        for warning in &check.warnings {
+            tracing::warn!("Stat {} differs from schema {} - {}", check.name, check.schema_id, warning);
+        }
+        if !check.errors.is_empty() {
+            failed_checks += 1;
+        }
+    }
+    if failed_checks > 0 && app_state.config.is_live_env() {
+        tracing::error!("Stat binding checks failed for {} stats, stopping", failed_checks);
+        return Err(format!("Stat binding checks failed for {} stats", failed_checks).into());
+    }
+
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await.unwrap();
+
     // Start PubSubPublisher tasks
     PubSubPublisher::start_stats_processing_tasks();
 
-    let app = routes::create_router(app_state);
+    // Start Redis health checks
+    RedisClient::start_health_check_task();
+
+    // Start listening for InstanceCache invalidations from other instances
+    InstanceCache::start_invalidation_listener();
+
+    // Start sweeping expired InstanceCache entries
+    InstanceCache::start_sweeper_task();
+
+    let app = routes::create_router(app_state.clone());
+
+    // Stop accepting requests on SIGTERM / SIGINT and finish the in-flight ones
+    let stop = CancellationToken::new();
+    let server = axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(stop.clone().cancelled_owned())
+        .into_future();
+    tokio::pin!(server);
+    let signalled = tokio::select! {
+        result = &mut server => {
+            result?;
+            false
+        },
+        _ = shutdown_signal() => {
+            stop.cancel();
+            true
+        },
+    };
+
+    // Finishing the in-flight requests and publishing the stats they buffered share the
+    // time from the signal, requests still running at the deadline are dropped
+    let deadline = Instant::now() + Duration::from_secs(app_state.config.pubsub.shutdown_timeout_secs);
+    if signalled {
+        match tokio::time::timeout_at(deadline, &mut server).await {
+            Ok(result) => result?,
+            Err(_) => tracing::error!("Requests still in flight at the shutdown deadline, dropped"),
+        }
+    }
 
-    axum::serve(listener, app.into_make_service()).await?;
+    // Publish the stats buffered by the finished requests, spilling what is left at the deadline
+    PubSubPublisher::shutdown(deadline).await;
+
+    tracing::info!("Application stopped");
 
     Ok(())
+}
+
+// Print the stat binding checks, returns whether any stat diverged from its topic or schema
+async fn check_stat_schemas(pubsub_client: &PubSubClient, project_id: &str) -> bool {
+    let derived_schemas = stat_registry::registered_stats().into_iter()
+        .map(|registered| (registered.name, registered.schema_json()))
+        .collect::<HashMap<_, _>>();
+
+    let mut diverged = false;
+    for check in stat_registry::check_registered_stats(pubsub_client, project_id).await {
+        if check.errors.is_empty() && check.warnings.is_empty() {
+            println!("OK    {} -> topic {}, schema {}", check.name, check.topic, check.schema_id);
+            continue;
+        }
+
+        diverged = true;
+        println!("DRIFT {} -> topic {}, schema {}", check.name, check.topic, check.schema_id);
+        for error in &check.errors {
+            println!("  error: {}", error);
+        }
+        for warning in &check.warnings {
+            println!("  warning: {}", warning);
+        }
+        match derived_schemas.get(check.name) {
+            Some(Ok(schema)) => println!("  schema derived from {}: {}", check.name, schema),
+            Some(Err(e)) => println!("  {}", e),
+            None => {},
+        }
+    }
+    diverged
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = signal::ctrl_c().await {
+            tracing::error!("Could not listen for SIGINT - err: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            },
+            Err(e) => {
+                tracing::error!("Could not listen for SIGTERM - err: {}", e);
+                std::future::pending::<()>().await;
+            },
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("Shutdown signal received, stopping application");
 }
\ No newline at end of file
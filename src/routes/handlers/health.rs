@@ -0,0 +1,42 @@
+use axum::http::StatusCode;
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::Router;
+use axum::routing::get;
+use serde_json::json;
+
+use crate::common_libs::utils::security_headers::v1::add_headers;
+use crate::state::APP_STATE;
+
+pub fn routes() -> Router {
+    Router::new()
+        .route("/", get(health_handler))
+}
+
+/// Always 200 while the app runs, requests are still served without Redis.
+///
+/// The Redis state is only reported, so that a Redis outage does not fail
+/// liveness probes and restart every instance.
+pub async fn health_handler() -> impl IntoResponse {
+    let app_state = APP_STATE.get().unwrap();
+    let security_headers = add_headers();
+
+    let redis_health = app_state.redis_client.health();
+
+    (
+        StatusCode::OK,
+        security_headers,
+        Json(json!({
+            "success": true,
+            "redis": {
+                "healthy": redis_health.healthy,
+                "health": redis_health,
+                "pool": app_state.redis_client.pool_stats(),
+                "circuit_breaker": app_state.redis_client.circuit_breaker_stats(),
+            },
+            "instance_cache": app_state.instance_cache.stats(),
+            "tiered_cache": app_state.tiered_cache.stats(),
+            "pubsub_publisher": app_state.pubsub_publisher.stats(),
+        }))
+    ).into_response()
+}
\ No newline at end of file
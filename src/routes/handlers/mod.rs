@@ -1,2 +1,3 @@
 pub mod general;
+pub mod health;
 pub mod test;
\ No newline at end of file
@@ -3,6 +3,7 @@ pub mod datastore_test;
 pub mod gcs_test;
 #[cfg(feature = "dev")]
 pub mod misc_test;
+pub mod pubsub_test;
 pub mod secret_manager_test;
 pub mod stats_test;
 
@@ -20,6 +21,7 @@ pub fn routes(app_state: Arc<AppState>) -> Router {
         .nest("/cache", cache_test::routes())
         .nest("/datastore", datastore_test::routes())
         .nest("/gcs", gcs_test::routes())
+        .nest("/pubsub", pubsub_test::routes())
         .nest("/secret_manager", secret_manager_test::routes());
 
     // Test routes for DEV environment ONLY
@@ -0,0 +1,87 @@
+#[cfg(feature = "dev")]
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::Router;
+use axum::routing::post;
+use serde_json::json;
+
+#[cfg(feature = "dev")]
+use crate::common_libs::pubsub::v1::{
+    models::test_stats::TestStats,
+    pubsub_push::push_route,
+    pubsub_subscriber::{MessageContext, MessageHandler},
+};
+use crate::common_libs::pubsub::v1::pubsub_push::PushAuthorized;
+use crate::common_libs::utils::security_headers::v1::add_headers;
+use crate::state::APP_STATE;
+
+pub fn routes() -> Router {
+    let router = Router::new()
+        .route("/replay", post(replay_spilled_stats));
+
+    // Push endpoint for the test subscription, DEV environment ONLY
+    #[cfg(feature = "dev")]
+    let router = router.route("/push", push_route(TestStatsHandler));
+
+    router
+}
+
+/// Authorized like push requests, to be called by Cloud Scheduler with the
+/// OIDC token or push token of the push subscriptions.
+pub async fn replay_spilled_stats(_: PushAuthorized) -> impl IntoResponse {
+    let app_state = APP_STATE.get().unwrap();
+    let security_headers = add_headers();
+
+    match app_state.pubsub_client.replay_spilled().await {
+        Ok(Some(report)) => {
+            (
+                StatusCode::OK,
+                security_headers,
+                Json(json!({
+                    "success": report.failed_batches == 0,
+                    "report": report,
+                }))
+            ).into_response()
+        },
+        Ok(None) => {
+            (
+                StatusCode::CONFLICT,
+                security_headers,
+                Json(json!({
+                    "success": false,
+                    "error": "Spilled stats are being replayed by another request"
+                }))
+            ).into_response()
+        },
+        Err(error) => {
+            tracing::error!("Error replaying spilled stats - err: {:?}", &error);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                security_headers,
+                Json(json!({
+                    "success": false,
+                    "error": format!("Could not replay spilled stats, err: {:?}", &error)
+                }))
+            ).into_response()
+        }
+    }
+}
+
+#[cfg(feature = "dev")]
+pub struct TestStatsHandler;
+
+#[cfg(feature = "dev")]
+#[async_trait]
+impl MessageHandler for TestStatsHandler {
+    type Message = TestStats;
+
+    async fn handle(&self, message: TestStats, context: &MessageContext) -> Result<(), String> {
+        tracing::info!(
+            "Received test_stats message {} from {} - event_type: {}",
+            context.message_id, context.subscription, message.event_type
+        );
+        Ok(())
+    }
+}
\ No newline at end of file
@@ -1,7 +1,7 @@
 use axum::extract::State;
 use axum::http::{HeaderMap, StatusCode};
 use axum::Json;
-use axum::response::IntoResponse;
+use axum::response::{IntoResponse, Response};
 use axum::Router;
 use axum::routing::post;
 use chrono::Utc;
@@ -9,8 +9,8 @@ use serde_json::json;
 use std::sync::Arc;
 
 use crate::common_libs::{
-    pubsub::v1::bigquery_constants::{bigquery_dataset, bigquery_table},
     pubsub::v1::models::test_stats::TestStats,
+    pubsub::v1::pubsub_publisher::PublishError,
     utils::{
         request_parser::v1::RequestContext,
         security_headers::v1::add_headers
@@ -65,11 +65,14 @@ pub async fn handle_test_stats(
         ad_refresh_rate: request.payload.remove("ad_refresh_rate").and_then(|s| s.parse::<i32>().ok()),
     });
 
-    match state.pubsub_publisher.publish(
-        bigquery_dataset::STATS.to_string(),
-        bigquery_table::TEST_STATS.to_string(),
-        bigquery_data,
-    ).await {
+    publish_response(state.pubsub_publisher.publish(bigquery_data).await)
+}
+
+// Shed stats are answered with a 503, so that clients retry later
+fn publish_response(result: Result<(), PublishError>) -> Response {
+    let security_headers = add_headers();
+
+    match result {
         Ok(_) => {
             return (
                 StatusCode::OK,
@@ -79,6 +82,17 @@ pub async fn handle_test_stats(
                 }))
             ).into_response();
         },
+        Err(PublishError::QueueFull) => {
+            tracing::warn!("Shed test_stats request, stat queue is full");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                security_headers,
+                Json(json!({
+                    "success": false,
+                    "error": "Too many stats queued, retry later"
+                }))
+            ).into_response()
+        },
         Err(error) => {
             tracing::error!("Error publishing test_stats request - err: {:?}", &error);
             return (
@@ -91,4 +105,31 @@ pub async fn handle_test_stats(
             ).into_response();
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common_libs::pubsub::v1::pubsub_publisher::PubSubPublisher;
+    use crate::config::{AppConfig, OverflowPolicy};
+
+    #[tokio::test]
+    async fn full_queue_answers_service_unavailable() {
+        let mut config = AppConfig::default().pubsub;
+        config.channel_capacity = 1;
+        config.overflow_policy = OverflowPolicy::Reject;
+        let publisher = PubSubPublisher::new(&config);
+
+        let accepted = publish_response(publisher.publish(make_stats!(TestStats { event_type: "a".to_string() })).await);
+        assert_eq!(accepted.status(), StatusCode::OK);
+
+        let shed = publish_response(publisher.publish(make_stats!(TestStats { event_type: "b".to_string() })).await);
+        assert_eq!(shed.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn closed_queue_answers_internal_error() {
+        let response = publish_response(Err(PublishError::Closed));
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
 }
\ No newline at end of file
@@ -3,6 +3,7 @@ pub mod handlers;
 use axum::body::Body;
 use axum::http::{header::{HeaderValue, self}, Method, StatusCode};
 use axum::response::IntoResponse;
+use axum::middleware;
 use axum::Router;
 use axum::routing::{any, get};
 use std::sync::Arc;
@@ -12,6 +13,7 @@ use tower_http::trace::TraceLayer;
 use tracing::Level;
 
 use crate::common_libs::utils::{
+    rate_limiter::v1::rate_limit,
     security_headers::v1::add_headers,
     structured_logging::v1::CustomMakeSpan,
 };
@@ -32,6 +34,7 @@ pub fn create_router(app_state: Arc<AppState>) -> Router {
 
     Router::new()
         .nest("/home", handlers::general::routes())
+        .nest("/health", handlers::health::routes())
         .nest("/app/rust_test", handlers::test::routes(app_state.clone()))
         .route("/robots.txt", get(|| async {
             let file = match tokio::fs::File::open("robots.txt").await {
@@ -47,6 +50,7 @@ pub fn create_router(app_state: Arc<AppState>) -> Router {
             );
             Ok((headers, body))
         }))
+        .layer(middleware::from_fn_with_state(app_state, rate_limit))
         .layer(cors)
         .layer(trace_layer)
         .fallback_service(any(|| async {
@@ -2,7 +2,12 @@ use once_cell::sync::OnceCell;
 use std::sync::Arc;
 
 use crate::common_libs::{
-    cache_service::v1::{instance_cache::InstanceCache, redis_client::RedisClient},
+    cache_service::v1::{
+        cache_key::CacheKeyBuilder,
+        instance_cache::InstanceCache,
+        redis_client::RedisClient,
+        tiered_cache::TieredCache,
+    },
     datastore::v1::datastore_client::DatastoreClient,
     gcs_storage::v1::gcs_client::GCSClient,
     pubsub::v1::{pubsub_client::PubSubClient, pubsub_publisher::PubSubPublisher},
@@ -18,27 +23,52 @@ pub struct AppState {
     pub pubsub_publisher: PubSubPublisher,
     pub redis_client: RedisClient,
     pub instance_cache: InstanceCache,
+    pub tiered_cache: TieredCache,
+    pub cache_keys: CacheKeyBuilder,
     pub datastore_client: DatastoreClient,
     pub gcs_client: GCSClient,
     pub secret_manager_client: SecretManagerClient,
 }
 
 impl AppState {
-    pub async fn new(config: AppConfig) -> Self {
+    pub async fn new(mut config: AppConfig) -> Self {
+        // Initialize SecretManagerClient
+        let secret_manager_client = SecretManagerClient::new(
+            config.google_cloud_project.clone()
+        ).await;
+
+        // Initialize RedisClient, the AUTH password is never cached
+        let redis_password = match config.redis.password_secret.as_str() {
+            "" => None,
+            secret => Some(secret_manager_client.fetch_secret_data(
+                secret,
+                &config.redis.password_secret_version
+            ).await.unwrap()),
+        };
+        let redis_client = RedisClient::new(&config.redis, redis_password);
+
+        // Resolve the Pub/Sub push token
+        if !config.pubsub.push_verification_token_secret.is_empty() {
+            config.pubsub.push_verification_token = secret_manager_client.fetch_secret_data(
+                &config.pubsub.push_verification_token_secret,
+                &config.pubsub.push_verification_token_secret_version
+            ).await.unwrap();
+        }
+
         // Initialize PubSub
         let pubsub_client = PubSubClient::new().await;
 
         // Initialize PubSubPublisher
-        let pubsub_publisher = PubSubPublisher::new();
-
-        // Initialize RedisClient
-        let redis_client = RedisClient::new(
-            &config.redishost,
-            config.redisport
-        );
+        let pubsub_publisher = PubSubPublisher::new(&config.pubsub);
 
         // Initialize InstanceCache
-        let instance_cache = InstanceCache::new();
+        let instance_cache = InstanceCache::new(&config.instance_cache);
+
+        // Initialize TieredCache
+        let tiered_cache = TieredCache::new();
+
+        // Initialize CacheKeyBuilder
+        let cache_keys = CacheKeyBuilder::new(&config);
 
         // Initialize DatastoreClient
         let datastore_client = DatastoreClient::new(
@@ -48,17 +78,14 @@ impl AppState {
         // Initialize GCSClient
         let gcs_client = GCSClient::new().await;
 
-        // Initialize SecretManagerClient
-        let secret_manager_client = SecretManagerClient::new(
-            config.google_cloud_project.clone()
-        ).await;
-
         Self {
             config,
             pubsub_client,
             pubsub_publisher,
             redis_client,
             instance_cache,
+            tiered_cache,
+            cache_keys,
             datastore_client,
             gcs_client,
             secret_manager_client,